@@ -1,7 +1,256 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+const ICON_DIR: &str = "assets/icons";
+const FONT_DIR: &str = "assets/fonts";
+
 fn main() {
     println!("cargo::rustc-check-cfg=cfg(esp_idf_version, values(\"4.3\"))");
     println!(
         "cargo::rustc-check-cfg=cfg(esp_idf_version_full, values(\"5.1.0\", \"5.1.1\", \"5.1.2\"))"
     );
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    generate_icons(Path::new(ICON_DIR), &out_dir.join("icons.rs"));
+    generate_fonts(Path::new(FONT_DIR), &out_dir.join("fonts.rs"));
+
     embuild::espidf::sysenv::output();
 }
+
+fn list_dir(dir: &Path) -> Vec<PathBuf> {
+    println!("cargo::rerun-if-changed={}", dir.display());
+
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+// `low-battery.svg` -> `LOW_BATTERY`
+fn const_name(path: &Path) -> String {
+    path.file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .to_uppercase()
+        .replace(['-', ' ', '.'], "_")
+}
+
+// Converts every PNG/SVG in `dir` into a packed 1-bit `Icon` constant named
+// after the file stem.
+fn generate_icons(dir: &Path, out: &Path) {
+    let mut code = String::new();
+    for path in list_dir(dir) {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        let (width, height, luma_alpha) = match ext {
+            "png" => load_png(&path),
+            "svg" => load_svg(&path),
+            _ => continue,
+        };
+        println!("cargo::rerun-if-changed={}", path.display());
+
+        let name = const_name(&path);
+        let data = pack_1bit(width, height, &luma_alpha);
+
+        writeln!(
+            code,
+            "pub const {name}: Icon = Icon {{ width: {width}, height: {height}, data: &{data:?} }};"
+        )
+        .unwrap();
+    }
+
+    std::fs::write(out, code).unwrap();
+}
+
+// Returns (width, height, [luma, alpha] per pixel).
+fn load_png(path: &Path) -> (u32, u32, Vec<[u8; 2]>) {
+    let file = std::fs::File::open(path).unwrap();
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder
+        .read_info()
+        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+
+    let pixels = buf[..info.buffer_size()]
+        .chunks(info.color_type.samples())
+        .map(|px| match *px {
+            [l] => [l, 255],
+            [l, a] => [l, a],
+            [r, g, b] => [luma(r, g, b), 255],
+            [r, g, b, a] => [luma(r, g, b), a],
+            _ => unreachable!(),
+        })
+        .collect();
+
+    (info.width, info.height, pixels)
+}
+
+fn load_svg(path: &Path) -> (u32, u32, Vec<[u8; 2]>) {
+    let data = std::fs::read(path).unwrap();
+    let tree = resvg::usvg::Tree::from_data(&data, &resvg::usvg::Options::default())
+        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+
+    let size = tree.size().to_int_size();
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(size.width(), size.height()).unwrap();
+    resvg::render(&tree, Default::default(), &mut pixmap.as_mut());
+
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .map(|px| {
+            let px = px.demultiply();
+            [luma(px.red(), px.green(), px.blue()), px.alpha()]
+        })
+        .collect();
+
+    (size.width(), size.height(), pixels)
+}
+
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+// Rows are MSB first and padded to whole bytes, a set bit means ink (black).
+fn pack_1bit(width: u32, height: u32, pixels: &[[u8; 2]]) -> Vec<u8> {
+    let stride = width.div_ceil(8) as usize;
+    let mut data = vec![0u8; stride * height as usize];
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let [l, a] = pixels[y * width as usize + x];
+            if a >= 128 && l < 128 {
+                data[y * stride + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+    data
+}
+
+// Converts every BDF in `dir` into a `BitmapFont` constant named after the
+// file stem. Glyphs are sorted by code point so lookups can binary search.
+fn generate_fonts(dir: &Path, out: &Path) {
+    let mut code = String::new();
+    for path in list_dir(dir) {
+        if path.extension().and_then(|e| e.to_str()) != Some("bdf") {
+            continue;
+        }
+        println!("cargo::rerun-if-changed={}", path.display());
+
+        let source = std::fs::read_to_string(&path).unwrap();
+        let font = parse_bdf(&source).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+
+        let mut glyphs = String::new();
+        let mut bitmap = Vec::new();
+        for g in &font.glyphs {
+            write!(
+                glyphs,
+                "Glyph {{ codepoint: {}, width: {}, height: {}, x_offset: {}, y_offset: {}, advance: {}, offset: {} }}, ",
+                g.codepoint, g.width, g.height, g.x_offset, g.y_offset, g.advance, bitmap.len()
+            )
+            .unwrap();
+            bitmap.extend_from_slice(&g.bitmap);
+        }
+
+        writeln!(
+            code,
+            "pub const {}: BitmapFont = BitmapFont {{ ascent: {}, descent: {}, default_char: {}, glyphs: &[{}], bitmap: &{:?} }};",
+            const_name(&path), font.ascent, font.descent, font.default_char, glyphs, bitmap
+        )
+        .unwrap();
+    }
+
+    std::fs::write(out, code).unwrap();
+}
+
+struct BdfFont {
+    ascent: i32,
+    descent: i32,
+    default_char: u32,
+    glyphs: Vec<BdfGlyph>,
+}
+
+#[derive(Default)]
+struct BdfGlyph {
+    codepoint: u32,
+    width: u32,
+    height: u32,
+    x_offset: i32,
+    y_offset: i32,
+    advance: i32,
+    bitmap: Vec<u8>,
+}
+
+// Minimal BDF 2.1 reader, only what is needed for horizontal bitmap text.
+fn parse_bdf(source: &str) -> Result<BdfFont, String> {
+    let mut font = BdfFont {
+        ascent: 0,
+        descent: 0,
+        default_char: '?' as u32,
+        glyphs: Vec::new(),
+    };
+    let mut glyph: Option<BdfGlyph> = None;
+    let mut in_bitmap = false;
+
+    let num = |s: Option<&str>| -> Result<i32, String> {
+        s.ok_or("missing field")?
+            .parse::<i32>()
+            .map_err(|e| e.to_string())
+    };
+
+    for line in source.lines() {
+        let mut fields = line.split_whitespace();
+        let keyword = fields.next().unwrap_or_default();
+
+        if in_bitmap {
+            let g = glyph.as_mut().unwrap();
+            if keyword == "ENDCHAR" {
+                in_bitmap = false;
+                let g = glyph.take().unwrap();
+                // ENCODING -1 marks glyphs without a code point
+                if g.codepoint != u32::MAX {
+                    font.glyphs.push(g);
+                }
+                continue;
+            }
+            let stride = g.width.div_ceil(8) as usize;
+            for i in 0..stride {
+                let byte = keyword
+                    .get(i * 2..i * 2 + 2)
+                    .ok_or_else(|| format!("short bitmap row '{keyword}'"))?;
+                g.bitmap
+                    .push(u8::from_str_radix(byte, 16).map_err(|e| e.to_string())?);
+            }
+            continue;
+        }
+
+        match keyword {
+            "FONT_ASCENT" => font.ascent = num(fields.next())?,
+            "FONT_DESCENT" => font.descent = num(fields.next())?,
+            "DEFAULT_CHAR" => font.default_char = num(fields.next())? as u32,
+            "STARTCHAR" => glyph = Some(BdfGlyph::default()),
+            "ENCODING" => {
+                let g = glyph.as_mut().ok_or("ENCODING outside STARTCHAR")?;
+                g.codepoint = u32::try_from(num(fields.next())?).unwrap_or(u32::MAX);
+            }
+            "DWIDTH" => {
+                glyph.as_mut().ok_or("DWIDTH outside STARTCHAR")?.advance = num(fields.next())?;
+            }
+            "BBX" => {
+                let g = glyph.as_mut().ok_or("BBX outside STARTCHAR")?;
+                g.width = num(fields.next())? as u32;
+                g.height = num(fields.next())? as u32;
+                g.x_offset = num(fields.next())?;
+                g.y_offset = num(fields.next())?;
+            }
+            "BITMAP" => in_bitmap = true,
+            _ => {}
+        }
+    }
+
+    font.glyphs.sort_by_key(|g| g.codepoint);
+    Ok(font)
+}
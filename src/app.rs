@@ -0,0 +1,404 @@
+use embedded_graphics::{
+    prelude::*,
+    primitives::Rectangle,
+    text::{Baseline, Text},
+};
+use epd_waveshare::color::Color;
+use tugger_core::battery::{BatteryLevel, BatteryStatus};
+use tugger_core::gesture::Gesture;
+
+use crate::assets::{self, Icon, IconStyle};
+use crate::button::{ButtonEvent, ButtonId};
+use crate::chart::{self, ChartKind, ChartStyle, History};
+use crate::composer::{Composer, DeliveryStatus};
+use crate::font::FontStyle;
+use crate::inbox::{Inbox, InboxView};
+use crate::power::RetainedState;
+use crate::qr::{self, ProvisioningInfo};
+use crate::radio::{TextPacket, BROADCAST};
+use crate::settings::Settings;
+use crate::ui;
+
+// The battery task posts on every percent step, the radio on every packet.
+const BATTERY_SAMPLES: usize = 64;
+const RSSI_SAMPLES: usize = 32;
+
+// Rows of the settings screen, a long press changes the selected one.
+const SETTINGS_ROWS: usize = 2;
+const ROW_ROTATION: usize = 0;
+const ROW_PROVISIONING: usize = 1;
+
+enum Screen {
+    Inbox(InboxView),
+    Composer(Composer),
+    Telemetry,
+    Settings { selected: usize },
+    // QR code with the node's identity for installers, any press closes it.
+    Provisioning,
+}
+
+// Screen navigation. With only the select button: a short press moves on, a
+// long press takes the current item and a double press switches to the next
+// screen, inbox, composer, telemetry, then settings. Boards with up/down
+// buttons get down = next (hold to scroll) and up = switch screen.
+pub struct App {
+    pub settings: Settings,
+    pub inbox: Inbox,
+    node_id: u32,
+    screen: Screen,
+    battery: Option<BatteryStatus>,
+    // Since boot only, a deep sleep starts them over.
+    battery_history: History<BATTERY_SAMPLES>,
+    rssi_history: History<RSSI_SAMPLES>,
+}
+
+// What the main loop has to do after an input.
+pub enum Action {
+    None,
+    Redraw,
+    Send(TextPacket),
+    // `settings` changed: store them and apply what the display depends on.
+    SettingsChanged,
+}
+
+impl App {
+    pub fn new(settings: Settings, inbox: Inbox, node_id: u32) -> Self {
+        Self {
+            settings,
+            inbox,
+            node_id,
+            screen: Screen::Inbox(InboxView::default()),
+            battery: None,
+            battery_history: History::new(),
+            rssi_history: History::new(),
+        }
+    }
+
+    // `size` is the drawable screen size, the inbox pages by it.
+    pub fn on_button(&mut self, event: ButtonEvent, size: Size) -> anyhow::Result<Action> {
+        let next = match (event.button, event.gesture) {
+            (ButtonId::Select, Gesture::Short) => true,
+            (ButtonId::Down, Gesture::Short | Gesture::Long | Gesture::Repeat) => true,
+            (ButtonId::Select, Gesture::Double) | (ButtonId::Up, Gesture::Short) => {
+                self.switch_screen();
+                return Ok(Action::Redraw);
+            }
+            (ButtonId::Select, Gesture::Long) => false,
+            _ => return Ok(Action::None),
+        };
+
+        match &mut self.screen {
+            Screen::Inbox(view) if next => view.next(&self.inbox, size),
+            Screen::Inbox(view) => view.select(&mut self.inbox)?,
+            Screen::Composer(composer) if next => composer.next(&self.settings),
+            Screen::Composer(composer) => {
+                if let Some(packet) = composer.select(&self.settings) {
+                    return Ok(Action::Send(packet));
+                }
+            }
+            Screen::Telemetry => return Ok(Action::None),
+            Screen::Settings { selected } if next => *selected = (*selected + 1) % SETTINGS_ROWS,
+            Screen::Settings { selected } => match *selected {
+                ROW_ROTATION => {
+                    self.settings.rotation = self.settings.rotation.next();
+                    return Ok(Action::SettingsChanged);
+                }
+                ROW_PROVISIONING => self.screen = Screen::Provisioning,
+                _ => return Ok(Action::None),
+            },
+            Screen::Provisioning => self.screen = Screen::Inbox(InboxView::default()),
+        }
+
+        Ok(Action::Redraw)
+    }
+
+    // A packet off the air. Ones for other nodes are dropped, known senders
+    // are shown by peer name.
+    pub fn on_message(&mut self, packet: TextPacket) -> anyhow::Result<Action> {
+        if packet.to != self.node_id && packet.to != BROADCAST {
+            return Ok(Action::None);
+        }
+        let sender = match self
+            .settings
+            .peers
+            .iter()
+            .find(|p| p.address == packet.from)
+        {
+            Some(peer) => peer.name.clone(),
+            None => format!("{:08x}", packet.from),
+        };
+        self.inbox.push(&sender, &packet.text)?;
+
+        // The new message lands on top: select it in the list, keep an open
+        // message open unless it was the oldest and got dropped for it.
+        let count = self.inbox.messages().len();
+        match &mut self.screen {
+            Screen::Inbox(InboxView::List { selected }) => *selected = 0,
+            Screen::Inbox(InboxView::Message { index, .. }) if *index + 1 < count => *index += 1,
+            Screen::Inbox(view @ InboxView::Message { .. }) => {
+                *view = InboxView::List { selected: 0 }
+            }
+            _ => {}
+        }
+        Ok(Action::Redraw)
+    }
+
+    // Signal strength of a received packet, for the telemetry chart.
+    pub fn on_rssi(&mut self, rssi: i16) -> Action {
+        self.rssi_history.push(rssi as f32);
+        self.telemetry_redraw()
+    }
+
+    // Outcome of an `Action::Send`.
+    pub fn delivery(&mut self, status: DeliveryStatus) {
+        if let Screen::Composer(composer) = &mut self.screen {
+            composer.set_status(status);
+        }
+    }
+
+    // Only a change of level is worth an e-paper refresh, the percentage is
+    // picked up with the next redraw.
+    pub fn on_battery(&mut self, status: BatteryStatus) -> Action {
+        self.battery_history.push(status.percent as f32);
+        let previous = self.battery.replace(status).map(|b| b.level);
+        if previous == Some(status.level) {
+            self.telemetry_redraw()
+        } else {
+            Action::Redraw
+        }
+    }
+
+    // A new sample only needs a refresh while its chart is up.
+    fn telemetry_redraw(&self) -> Action {
+        match self.screen {
+            Screen::Telemetry => Action::Redraw,
+            _ => Action::None,
+        }
+    }
+
+    // Opens on the QR code, for a unit that hasn't been set up yet.
+    pub fn show_provisioning(&mut self) {
+        self.screen = Screen::Provisioning;
+    }
+
+    // Where the user was, to come back to after a deep sleep.
+    pub fn retained(&self) -> RetainedState {
+        match self.screen {
+            Screen::Inbox(InboxView::List { selected }) => RetainedState {
+                screen: 0,
+                index: selected as u32,
+                line: 0,
+            },
+            Screen::Inbox(InboxView::Message { index, first_line }) => RetainedState {
+                screen: 1,
+                index: index as u32,
+                line: first_line as u32,
+            },
+            // A half composed reply is not worth keeping, start it over.
+            Screen::Composer(_) => RetainedState {
+                screen: 2,
+                ..RetainedState::default()
+            },
+            Screen::Telemetry => RetainedState {
+                screen: 3,
+                ..RetainedState::default()
+            },
+            Screen::Settings { selected } => RetainedState {
+                screen: 4,
+                index: selected as u32,
+                line: 0,
+            },
+            // Nobody is waiting on it after a sleep, wake to the inbox.
+            Screen::Provisioning => RetainedState::default(),
+        }
+    }
+
+    pub fn restore(&mut self, state: RetainedState) {
+        let index = state.index as usize;
+        let count = self.inbox.messages().len();
+        self.screen = match state.screen {
+            1 if index < count => Screen::Inbox(InboxView::Message {
+                index,
+                first_line: state.line as usize,
+            }),
+            2 => Screen::Composer(Composer::new(self.node_id)),
+            3 => Screen::Telemetry,
+            4 => Screen::Settings {
+                selected: index.min(SETTINGS_ROWS - 1),
+            },
+            _ => Screen::Inbox(InboxView::List {
+                selected: index.min(count.saturating_sub(1)),
+            }),
+        };
+    }
+
+    fn switch_screen(&mut self) {
+        self.screen = match self.screen {
+            Screen::Inbox(_) => Screen::Composer(Composer::new(self.node_id)),
+            Screen::Composer(_) => Screen::Telemetry,
+            Screen::Telemetry => Screen::Settings { selected: 0 },
+            Screen::Settings { .. } | Screen::Provisioning => Screen::Inbox(InboxView::default()),
+        };
+    }
+
+    pub fn draw<D>(&self, target: &mut D) -> anyhow::Result<()>
+    where
+        D: DrawTarget<Color = Color>,
+    {
+        match &self.screen {
+            Screen::Inbox(view) => view.draw(target, &self.inbox)?,
+            Screen::Composer(composer) => composer.draw(target, &self.settings)?,
+            Screen::Telemetry => draw_telemetry(target, &self.battery_history, &self.rssi_history)?,
+            Screen::Settings { selected } => draw_settings(target, &self.settings, *selected)?,
+            Screen::Provisioning => {
+                let node_id = format!("{:08x}", self.node_id);
+                let info = ProvisioningInfo {
+                    node_id: &node_id,
+                    key_fingerprint: &self.settings.key_fingerprint(),
+                    url: &self.settings.provisioning_url,
+                };
+                let area = target.bounding_box();
+                qr::draw_provisioning(target, &info, area)?
+            }
+        }
+
+        match self.battery {
+            Some(status) if status.level == BatteryLevel::Low => draw_low_battery(target, status),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn draw_settings<D>(target: &mut D, settings: &Settings, selected: usize) -> anyhow::Result<()>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let rows = [
+        format!("Rotation {}°", settings.rotation.degrees()),
+        "Provisioning QR".to_string(),
+    ];
+    ui::draw_header(target, "Settings", "")
+        .and_then(|_| ui::draw_list(target, &rows, selected, ""))
+        .map_err(|_| anyhow::anyhow!("Settings draw failed"))
+}
+
+// Battery charge over its last steps and the RSSI of the last packets, one
+// chart each in the top and bottom half of the body. The RSSI one is marked
+// with the signal icon.
+fn draw_telemetry<D>(
+    target: &mut D,
+    battery: &History<BATTERY_SAMPLES>,
+    rssi: &History<RSSI_SAMPLES>,
+) -> anyhow::Result<()>
+where
+    D: DrawTarget<Color = Color>,
+{
+    ui::draw_header(target, "Telemetry", "")
+        .map_err(|_| anyhow::anyhow!("Telemetry draw failed"))?;
+
+    let body = ui::body_area(target.bounding_box());
+    let half = Size::new(body.size.width, body.size.height / 2);
+    let lower = body.top_left + Point::new(0, half.height as i32);
+    draw_trace(
+        target,
+        None,
+        "Battery",
+        "%",
+        battery,
+        Rectangle::new(body.top_left, half),
+        ChartKind::Sparkline,
+    )?;
+    draw_trace(
+        target,
+        Some(&assets::SIGNAL),
+        "RSSI",
+        " dBm",
+        rssi,
+        Rectangle::new(lower, half),
+        ChartKind::Bars,
+    )
+}
+
+// The icon if any, name and latest value on one line, the chart below it.
+fn draw_trace<D, const N: usize>(
+    target: &mut D,
+    icon: Option<&Icon>,
+    name: &str,
+    unit: &str,
+    history: &History<N>,
+    area: Rectangle,
+    kind: ChartKind,
+) -> anyhow::Result<()>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let font = ui::body_font();
+    let mut title_origin = area.top_left;
+    let mut line = font.line_height();
+    if let Some(icon) = icon {
+        assets::draw_icon(target, icon, area.top_left, IconStyle::default())?;
+        title_origin.x += icon.width as i32 + ui::MARGIN;
+        line = line.max(icon.height);
+    }
+
+    let title = match history.last() {
+        Some(value) => format!("{} {:.0}{}", name, value, unit),
+        None => format!("{} -", name),
+    };
+    Text::with_baseline(
+        &title,
+        title_origin,
+        FontStyle::new(font, Color::Black),
+        Baseline::Top,
+    )
+    .draw(target)
+    .map_err(|_| anyhow::anyhow!("Telemetry draw failed"))?;
+
+    let plot = Rectangle::new(
+        area.top_left + Point::new(0, line as i32),
+        Size::new(
+            area.size.width,
+            area.size.height.saturating_sub(line + ui::MARGIN as u32),
+        ),
+    );
+    let style = ChartStyle {
+        kind,
+        ..ChartStyle::default()
+    };
+    chart::draw_chart(target, history, plot, style)
+}
+
+// Inverted battery icon and percentage in the bottom right corner.
+fn draw_low_battery<D>(target: &mut D, status: BatteryStatus) -> anyhow::Result<()>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let screen = target.bounding_box();
+    let corner = screen.top_left + screen.size - Size::new(1, 1);
+    let icon = &assets::BATTERY;
+    let font = ui::body_font();
+    let text = format!("{}%", status.percent);
+
+    let icon_origin = corner
+        - Point::new(
+            icon.width as i32 + ui::MARGIN,
+            icon.height as i32 + ui::MARGIN,
+        );
+    let style = IconStyle {
+        invert: true,
+        ..IconStyle::default()
+    };
+    assets::draw_icon(target, icon, icon_origin, style)?;
+
+    let text_origin = Point::new(
+        icon_origin.x - ui::MARGIN - font.text_width(&text) as i32,
+        icon_origin.y + icon.height as i32,
+    );
+    let mut style = FontStyle::new(font, Color::Black);
+    style.background = Some(Color::White);
+    Text::with_baseline(&text, text_origin, style, Baseline::Bottom)
+        .draw(target)
+        .map_err(|_| anyhow::anyhow!("Battery indicator draw failed"))?;
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+use epd_waveshare::color::Color;
+
+// Icons are converted from `assets/icons` by build.rs.
+include!(concat!(env!("OUT_DIR"), "/icons.rs"));
+
+// Packed 1-bit image, rows MSB first and padded to whole bytes. A set bit is ink.
+pub struct Icon {
+    pub width: u32,
+    pub height: u32,
+    pub data: &'static [u8],
+}
+
+impl Icon {
+    pub fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+
+    pub fn is_set(&self, x: u32, y: u32) -> bool {
+        let stride = self.width.div_ceil(8);
+        let byte = self.data[(y * stride + x / 8) as usize];
+        byte & (0x80 >> (x % 8)) != 0
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct IconStyle {
+    // Draw white ink on a black box instead of black ink on whatever is below.
+    pub invert: bool,
+    // Integer upscale factor, nearest neighbour.
+    pub scale: u32,
+}
+
+impl Default for IconStyle {
+    fn default() -> Self {
+        Self {
+            invert: false,
+            scale: 1,
+        }
+    }
+}
+
+// Draws `icon` with its top left corner at `top_left` and returns the covered area.
+pub fn draw_icon<D>(
+    target: &mut D,
+    icon: &Icon,
+    top_left: Point,
+    style: IconStyle,
+) -> anyhow::Result<Rectangle>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let scale = style.scale.max(1);
+    let area = Rectangle::new(top_left, icon.size() * scale);
+
+    let (ink, paper) = if style.invert {
+        (Color::White, Color::Black)
+    } else {
+        (Color::Black, Color::White)
+    };
+
+    if style.invert {
+        target
+            .fill_solid(&area, paper)
+            .map_err(|_| anyhow::anyhow!("Icon draw failed"))?;
+    }
+
+    let pixels = (0..icon.height)
+        .flat_map(|y| (0..icon.width).map(move |x| (x, y)))
+        .filter(|&(x, y)| icon.is_set(x, y))
+        .flat_map(|(x, y)| {
+            (0..scale * scale).map(move |i| {
+                let p = Point::new(
+                    (x * scale + i % scale) as i32,
+                    (y * scale + i / scale) as i32,
+                );
+                Pixel(top_left + p, ink)
+            })
+        });
+
+    target
+        .draw_iter(pixels)
+        .map_err(|_| anyhow::anyhow!("Icon draw failed"))?;
+
+    Ok(area)
+}
@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use esp_idf_hal::adc::Adc;
+use esp_idf_hal::gpio::*;
+use esp_idf_hal::sys::*;
+use log::*;
+use tugger_core::battery::{BatteryMonitor, BatteryStatus};
+
+use crate::event::{Event, Sender};
+use crate::hardware::BatterySense;
+
+const SAMPLES_PER_READING: u32 = 16;
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Oneshot ADC read of the divider tap through ESP-IDF, with the eFuse curve
+// fitting calibration turning raw counts into mV at the pin.
+pub struct BatteryAdc<A> {
+    // The ADC and the pin sensed on it, held so nothing else claims them.
+    _adc: A,
+    _pin: AnyIOPin,
+    unit: adc_oneshot_unit_handle_t,
+    cali: adc_cali_handle_t,
+    channel: adc_channel_t,
+    // Pin and its active level.
+    enable: Option<(PinDriver<'static, AnyOutputPin, Output>, bool)>,
+    divider: f32,
+}
+
+// The handles are only used from the battery task.
+unsafe impl<A: Send> Send for BatteryAdc<A> {}
+
+impl<A: Adc> BatteryAdc<A> {
+    // `sense` is the board's `battery`, `adc` is claimed from
+    // `hardware::Resources`.
+    pub fn new(sense: BatterySense, adc: A) -> anyhow::Result<Self> {
+        let BatterySense {
+            profile,
+            adc: pin,
+            enable,
+        } = sense;
+        let mut unit_id: adc_unit_t = 0;
+        let mut channel: adc_channel_t = 0;
+        esp!(unsafe { adc_oneshot_io_to_channel(pin.pin(), &mut unit_id, &mut channel) })?;
+        if unit_id != A::unit() {
+            anyhow::bail!(
+                "Battery sense GPIO{} is on ADC{}, not ADC{}",
+                pin.pin(),
+                unit_id + 1,
+                A::unit() + 1
+            );
+        }
+
+        let mut unit = std::ptr::null_mut();
+        let unit_config = adc_oneshot_unit_init_cfg_t {
+            unit_id,
+            ..Default::default()
+        };
+        esp!(unsafe { adc_oneshot_new_unit(&unit_config, &mut unit) })?;
+
+        // 12 dB covers the pin up to ~3.1 V.
+        let atten = adc_atten_t_ADC_ATTEN_DB_12;
+        let bitwidth = adc_bitwidth_t_ADC_BITWIDTH_DEFAULT;
+        let channel_config = adc_oneshot_chan_cfg_t { atten, bitwidth };
+        esp!(unsafe { adc_oneshot_config_channel(unit, channel, &channel_config) })?;
+
+        let mut cali = std::ptr::null_mut();
+        let cali_config = adc_cali_curve_fitting_config_t {
+            unit_id,
+            chan: channel,
+            atten,
+            bitwidth,
+        };
+        esp!(unsafe { adc_cali_create_scheme_curve_fitting(&cali_config, &mut cali) })?;
+
+        let enable = match enable {
+            Some(mut pin) => {
+                let active = profile.enable_active_high;
+                pin.set_level((!active).into())?;
+                Some((pin, active))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            _adc: adc,
+            _pin: pin,
+            unit,
+            cali,
+            channel,
+            enable,
+            divider: profile.divider,
+        })
+    }
+
+    // Battery voltage in mV, averaged over a burst of samples.
+    pub fn read_millivolts(&mut self) -> anyhow::Result<u32> {
+        if let Some((pin, active)) = &mut self.enable {
+            pin.set_level((*active).into())?;
+            // Let the divider and the sampling capacitor settle.
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let result = self.sample();
+
+        if let Some((pin, active)) = &mut self.enable {
+            pin.set_level((!*active).into())?;
+        }
+
+        let pin_mv = result?;
+        Ok((pin_mv as f32 * self.divider).round() as u32)
+    }
+
+    fn sample(&mut self) -> anyhow::Result<u32> {
+        let mut sum = 0;
+        for _ in 0..SAMPLES_PER_READING {
+            let mut raw = 0;
+            esp!(unsafe { adc_oneshot_read(self.unit, self.channel, &mut raw) })?;
+            let mut mv = 0;
+            esp!(unsafe { adc_cali_raw_to_voltage(self.cali, raw, &mut mv) })?;
+            sum += mv.max(0) as u32;
+        }
+        Ok(sum / SAMPLES_PER_READING)
+    }
+}
+
+// Starts the battery task. It samples every `SAMPLE_INTERVAL` and posts a
+// status whenever the level or the rounded percentage changes.
+pub fn spawn<A: Adc + Send + 'static>(
+    mut adc: BatteryAdc<A>,
+    mut monitor: BatteryMonitor,
+    events: Sender,
+) -> anyhow::Result<()> {
+    std::thread::Builder::new()
+        .name("battery".into())
+        .stack_size(4096)
+        .spawn(move || {
+            let mut last: Option<BatteryStatus> = None;
+            loop {
+                match adc.read_millivolts() {
+                    Ok(mv) => {
+                        let status = monitor.update(mv);
+                        let changed = last.map_or(true, |l| {
+                            l.level != status.level || l.percent != status.percent
+                        });
+                        if changed {
+                            debug!("Battery {:?}", status);
+                            if events.send(Event::Battery(status)).is_err() {
+                                return;
+                            }
+                            last = Some(status);
+                        }
+                    }
+                    Err(e) => warn!("Battery read failed: {:?}", e),
+                }
+                std::thread::sleep(SAMPLE_INTERVAL);
+            }
+        })?;
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use esp_idf_hal::gpio::*;
+
+use crate::panel::PanelKind;
+
+// One supported board. The profile is picked at build time with a `board-*`
+// cargo feature, see `BOARD`.
+pub struct BoardProfile {
+    pub name: &'static str,
+    // Moves the board's GPIOs out of the HAL's `Pins`.
+    pub pins: fn(Pins) -> BoardPins,
+    // Glass fitted at the factory, used when the settings leave it on `Auto`.
+    pub panel: PanelKind,
+    // Present when `BoardPins::battery_adc` is.
+    pub battery: Option<BatteryProfile>,
+    pub vext_active_low: bool,
+    // Present when `BoardPins::gnss` is.
+    pub gnss: Option<UartProfile>,
+}
+
+// GPIO assignment, each pin moved out of `Pins` by field. Taking a GPIO twice
+// is a use of a moved value, so the compiler rejects it.
+pub struct BoardPins {
+    // Shared SPI bus for the radio and the display.
+    pub spi_sclk: AnyIOPin,
+    pub spi_mosi: AnyIOPin,
+    pub spi_miso: AnyIOPin,
+    pub lora_nss: AnyOutputPin,
+    pub lora_rst: AnyOutputPin,
+    pub lora_busy: AnyInputPin,
+    pub lora_dio1: AnyInputPin,
+    pub display_cs: AnyOutputPin,
+    pub display_dc: AnyOutputPin,
+    pub display_rst: AnyOutputPin,
+    pub display_busy: AnyInputPin,
+    pub btn_select: AnyIOPin,
+    // Not every board has room for navigation buttons.
+    pub btn_up: Option<AnyIOPin>,
+    pub btn_down: Option<AnyIOPin>,
+    // Battery voltage divider tap, on an ADC capable pin.
+    pub battery_adc: Option<AnyIOPin>,
+    // Switches the divider in, so it doesn't drain the cell between readings.
+    pub battery_enable: Option<AnyOutputPin>,
+    // Switched supply for the display and external peripherals.
+    pub vext: Option<AnyOutputPin>,
+    // Status LED, active high.
+    pub led: Option<AnyOutputPin>,
+    // Expansion bus for telemetry sensors, powered from `vext` where present.
+    pub i2c: Option<I2cPins>,
+    // UART GNSS receiver.
+    pub gnss: Option<UartPins>,
+}
+
+pub struct I2cPins {
+    pub sda: AnyIOPin,
+    pub scl: AnyIOPin,
+}
+
+pub struct UartPins {
+    // Our receive pin, wired to the module's TX.
+    pub rx: AnyIOPin,
+    pub tx: AnyIOPin,
+}
+
+pub struct UartProfile {
+    pub baudrate: u32,
+}
+
+pub struct BatteryProfile {
+    pub enable_active_high: bool,
+    // Battery voltage over the voltage at the ADC pin.
+    pub divider: f32,
+}
+
+// Heltec Wireless Paper V1.0, V1.1 and V1.2 share one layout.
+#[cfg(any(
+    feature = "board-heltec-v1_0",
+    feature = "board-heltec-v1_1",
+    feature = "board-heltec-v1_2"
+))]
+fn heltec_wireless_paper_pins(pins: Pins) -> BoardPins {
+    BoardPins {
+        spi_sclk: pins.gpio9.downgrade(),
+        spi_mosi: pins.gpio10.downgrade(),
+        spi_miso: pins.gpio11.downgrade(),
+        lora_nss: pins.gpio8.downgrade_output(),
+        lora_rst: pins.gpio12.downgrade_output(),
+        lora_busy: pins.gpio13.downgrade_input(),
+        lora_dio1: pins.gpio14.downgrade_input(),
+        display_cs: pins.gpio4.downgrade_output(),
+        display_dc: pins.gpio5.downgrade_output(),
+        display_rst: pins.gpio6.downgrade_output(),
+        display_busy: pins.gpio7.downgrade_input(),
+        btn_select: pins.gpio0.downgrade(),
+        btn_up: None,
+        btn_down: None,
+        battery_adc: Some(pins.gpio20.downgrade()),
+        battery_enable: Some(pins.gpio19.downgrade_output()),
+        vext: Some(pins.gpio45.downgrade_output()),
+        led: Some(pins.gpio18.downgrade_output()),
+        // On the expansion header.
+        i2c: Some(I2cPins {
+            sda: pins.gpio41.downgrade(),
+            scl: pins.gpio42.downgrade(),
+        }),
+        // Also on the expansion header.
+        gnss: Some(UartPins {
+            rx: pins.gpio39.downgrade(),
+            tx: pins.gpio40.downgrade(),
+        }),
+    }
+}
+
+#[cfg(any(
+    feature = "board-heltec-v1_0",
+    feature = "board-heltec-v1_1",
+    feature = "board-heltec-v1_2"
+))]
+const HELTEC_WIRELESS_PAPER_V1_0: BoardProfile = BoardProfile {
+    name: "Heltec Wireless Paper V1.0",
+    pins: heltec_wireless_paper_pins,
+    panel: PanelKind::Epd2in9V2,
+    // 390k over 100k.
+    battery: Some(BatteryProfile {
+        enable_active_high: true,
+        divider: 4.9,
+    }),
+    vext_active_low: true,
+    // The usual module default rate.
+    gnss: Some(UartProfile { baudrate: 9600 }),
+};
+
+#[cfg(feature = "board-heltec-v1_1")]
+const HELTEC_WIRELESS_PAPER_V1_1: BoardProfile = BoardProfile {
+    name: "Heltec Wireless Paper V1.1",
+    ..HELTEC_WIRELESS_PAPER_V1_0
+};
+
+// Same wiring, but V1.2 units have shipped with glass from more than one
+// vendor, so the controller is probed at boot.
+#[cfg(feature = "board-heltec-v1_2")]
+const HELTEC_WIRELESS_PAPER_V1_2: BoardProfile = BoardProfile {
+    name: "Heltec Wireless Paper V1.2",
+    panel: PanelKind::Auto,
+    ..HELTEC_WIRELESS_PAPER_V1_0
+};
+
+// Bare ESP32-S3 devkit with an SX1262 breakout on the FSPI IO_MUX pins and a
+// Waveshare e-paper module. Pins 26-37 are avoided, they carry flash/PSRAM on
+// most modules.
+#[cfg(feature = "board-generic-s3")]
+fn generic_esp32s3_pins(pins: Pins) -> BoardPins {
+    BoardPins {
+        spi_sclk: pins.gpio12.downgrade(),
+        spi_mosi: pins.gpio11.downgrade(),
+        spi_miso: pins.gpio13.downgrade(),
+        lora_nss: pins.gpio10.downgrade_output(),
+        lora_rst: pins.gpio5.downgrade_output(),
+        lora_busy: pins.gpio4.downgrade_input(),
+        lora_dio1: pins.gpio6.downgrade_input(),
+        display_cs: pins.gpio15.downgrade_output(),
+        display_dc: pins.gpio16.downgrade_output(),
+        display_rst: pins.gpio17.downgrade_output(),
+        display_busy: pins.gpio18.downgrade_input(),
+        btn_select: pins.gpio0.downgrade(),
+        btn_up: Some(pins.gpio38.downgrade()),
+        btn_down: Some(pins.gpio39.downgrade()),
+        battery_adc: None,
+        battery_enable: None,
+        vext: None,
+        // The devkit's RGB LED is addressable, not a plain GPIO.
+        led: None,
+        i2c: Some(I2cPins {
+            sda: pins.gpio8.downgrade(),
+            scl: pins.gpio9.downgrade(),
+        }),
+        gnss: Some(UartPins {
+            rx: pins.gpio2.downgrade(),
+            tx: pins.gpio1.downgrade(),
+        }),
+    }
+}
+
+#[cfg(feature = "board-generic-s3")]
+const GENERIC_ESP32S3_SX1262: BoardProfile = BoardProfile {
+    name: "Generic ESP32-S3 + SX1262",
+    pins: generic_esp32s3_pins,
+    panel: PanelKind::Auto,
+    battery: None,
+    vext_active_low: false,
+    gnss: Some(UartProfile { baudrate: 9600 }),
+};
+
+#[cfg(feature = "board-heltec-v1_0")]
+pub const BOARD: BoardProfile = HELTEC_WIRELESS_PAPER_V1_0;
+#[cfg(feature = "board-heltec-v1_1")]
+pub const BOARD: BoardProfile = HELTEC_WIRELESS_PAPER_V1_1;
+#[cfg(feature = "board-heltec-v1_2")]
+pub const BOARD: BoardProfile = HELTEC_WIRELESS_PAPER_V1_2;
+#[cfg(feature = "board-generic-s3")]
+pub const BOARD: BoardProfile = GENERIC_ESP32S3_SX1262;
+
+const SELECTED_BOARDS: usize = cfg!(feature = "board-heltec-v1_0") as usize
+    + cfg!(feature = "board-heltec-v1_1") as usize
+    + cfg!(feature = "board-heltec-v1_2") as usize
+    + cfg!(feature = "board-generic-s3") as usize;
+
+const _: () = assert!(
+    SELECTED_BOARDS == 1,
+    "Enable exactly one board-* feature (use --no-default-features to change board)"
+);
@@ -0,0 +1,99 @@
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::delay::{TickType, BLOCK};
+use esp_idf_hal::gpio::*;
+use esp_idf_hal::task::notification::Notification;
+use log::*;
+use tugger_core::gesture::{Gesture, GestureDetector, Timings};
+
+use crate::event::{Event, Sender};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonId {
+    Select,
+    Up,
+    Down,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ButtonEvent {
+    pub button: ButtonId,
+    pub gesture: Gesture,
+}
+
+// How often the button task samples while a gesture is in progress. Between
+// gestures it sleeps until the next edge interrupt.
+const TICK: Duration = Duration::from_millis(10);
+
+// Starts the button task. Buttons are active low, `hardware::init` enables
+// their pull-ups. Every edge interrupt wakes the task, which samples all
+// buttons and posts gestures to `events`.
+pub fn spawn(
+    buttons: Vec<(ButtonId, PinDriver<'static, AnyIOPin, Input>)>,
+    events: Sender,
+) -> anyhow::Result<()> {
+    std::thread::Builder::new()
+        .name("buttons".into())
+        .stack_size(4096)
+        .spawn(move || {
+            if let Err(e) = run(buttons, events) {
+                error!("Button task stopped: {:?}", e);
+            }
+        })?;
+    Ok(())
+}
+
+fn run(
+    buttons: Vec<(ButtonId, PinDriver<'static, AnyIOPin, Input>)>,
+    events: Sender,
+) -> anyhow::Result<()> {
+    // The notification has to be created on the task that waits on it.
+    let notification = Notification::new();
+
+    let mut buttons: Vec<_> = buttons
+        .into_iter()
+        .map(|(id, pin)| (id, pin, GestureDetector::new(Timings::default())))
+        .collect();
+
+    for (_, pin, _) in &mut buttons {
+        pin.set_interrupt_type(InterruptType::AnyEdge)?;
+        let notifier = notification.notifier();
+        unsafe {
+            pin.subscribe(move || {
+                notifier.notify_and_yield(NonZeroU32::new(1).unwrap());
+            })?;
+        }
+    }
+
+    let start = Instant::now();
+    loop {
+        // Interrupts disarm themselves after firing.
+        for (_, pin, _) in &mut buttons {
+            pin.enable_interrupt()?;
+        }
+
+        let timeout = if buttons.iter().any(|(_, _, d)| d.is_active()) {
+            TickType::from(TICK).ticks()
+        } else {
+            BLOCK
+        };
+        notification.wait(timeout);
+
+        let now = start.elapsed();
+        for (id, pin, detector) in &mut buttons {
+            detector.input(pin.is_low(), now);
+            while let Some(gesture) = detector.poll(now) {
+                debug!("Button {:?} {:?}", id, gesture);
+                let event = ButtonEvent {
+                    button: *id,
+                    gesture,
+                };
+                // The receiver only goes away when the app is shutting down.
+                if events.send(Event::Button(event)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
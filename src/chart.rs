@@ -0,0 +1,240 @@
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use epd_waveshare::color::Color;
+
+const LABEL_GAP: u32 = 2;
+
+// Fixed size ring buffer of telemetry samples, oldest are overwritten first.
+pub struct History<const N: usize> {
+    samples: [f32; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> History<N> {
+    pub const fn new() -> Self {
+        // `push` wraps around modulo N.
+        const { assert!(N > 0, "History needs room for a sample") };
+        Self {
+            samples: [0.0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, value: f32) {
+        self.samples[self.head] = value;
+        self.head = (self.head + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = f32> + '_ {
+        let start = (self.head + N - self.len) % N;
+        (0..self.len).map(move |i| self.samples[(start + i) % N])
+    }
+
+    pub fn last(&self) -> Option<f32> {
+        self.iter().last()
+    }
+
+    pub fn min(&self) -> Option<f32> {
+        self.iter().reduce(f32::min)
+    }
+
+    pub fn max(&self) -> Option<f32> {
+        self.iter().reduce(f32::max)
+    }
+}
+
+impl<const N: usize> Default for History<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ChartKind {
+    Sparkline,
+    // Bars grow from zero for non-negative data (packet counts), otherwise from the minimum.
+    Bars,
+}
+
+#[derive(Clone, Copy)]
+pub struct ChartStyle {
+    pub kind: ChartKind,
+    // Print the max at the top and the min at the bottom, left of the plot.
+    pub min_max_labels: bool,
+    pub decimals: usize,
+}
+
+impl Default for ChartStyle {
+    fn default() -> Self {
+        Self {
+            kind: ChartKind::Sparkline,
+            min_max_labels: true,
+            decimals: 0,
+        }
+    }
+}
+
+// Renders `history` into `area`. The newest sample is always at the right edge;
+// with more samples than pixels the sparkline overlaps and bars keep the newest.
+pub fn draw_chart<D, const N: usize>(
+    target: &mut D,
+    history: &History<N>,
+    area: Rectangle,
+    style: ChartStyle,
+) -> anyhow::Result<()>
+where
+    D: DrawTarget<Color = Color>,
+{
+    target
+        .fill_solid(&area, Color::White)
+        .map_err(|_| anyhow::anyhow!("Chart draw failed"))?;
+
+    let (Some(min), Some(max)) = (history.min(), history.max()) else {
+        return Ok(());
+    };
+
+    let plot = if style.min_max_labels {
+        draw_labels(target, area, min, max, style.decimals)?
+    } else {
+        area
+    };
+    if plot.size.width < 2 || plot.size.height < 2 {
+        return Ok(());
+    }
+
+    match style.kind {
+        ChartKind::Sparkline => draw_sparkline(target, history, plot, min, max),
+        ChartKind::Bars => {
+            let low = if min >= 0.0 { 0.0 } else { min };
+            draw_bars(target, history, plot, low, max)
+        }
+    }
+    .map_err(|_| anyhow::anyhow!("Chart draw failed"))
+}
+
+// Returns the area left over for the plot.
+fn draw_labels<D>(
+    target: &mut D,
+    area: Rectangle,
+    min: f32,
+    max: f32,
+    decimals: usize,
+) -> anyhow::Result<Rectangle>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let max_label = format!("{:.*}", decimals, max);
+    let min_label = format!("{:.*}", decimals, min);
+    let chars = max_label.len().max(min_label.len()) as u32;
+    let label_width = chars * FONT_6X10.character_size.width + LABEL_GAP;
+
+    let style = MonoTextStyle::new(&FONT_6X10, Color::Black);
+    let bottom = area.top_left + Point::new(0, area.size.height as i32);
+    Text::with_baseline(&max_label, area.top_left, style, Baseline::Top)
+        .draw(target)
+        .map_err(|_| anyhow::anyhow!("Chart draw failed"))?;
+    Text::with_baseline(&min_label, bottom, style, Baseline::Bottom)
+        .draw(target)
+        .map_err(|_| anyhow::anyhow!("Chart draw failed"))?;
+
+    Ok(Rectangle::new(
+        area.top_left + Point::new(label_width as i32, 0),
+        Size::new(
+            area.size.width.saturating_sub(label_width),
+            area.size.height,
+        ),
+    ))
+}
+
+// Maps `value` in `low..=high` to a y coordinate inside `plot`, high at the top.
+fn scale_y(plot: Rectangle, value: f32, low: f32, high: f32) -> i32 {
+    let span = (high - low).max(f32::EPSILON);
+    let bottom = plot.top_left.y + plot.size.height as i32 - 1;
+    let range = (plot.size.height - 1) as f32;
+    let fraction = if high > low {
+        (value - low) / span
+    } else {
+        0.5
+    };
+    bottom - (fraction.clamp(0.0, 1.0) * range).round() as i32
+}
+
+fn draw_sparkline<D, const N: usize>(
+    target: &mut D,
+    history: &History<N>,
+    plot: Rectangle,
+    min: f32,
+    max: f32,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let stroke = PrimitiveStyle::with_stroke(Color::Black, 1);
+    let right = plot.top_left.x + plot.size.width as i32 - 1;
+    let steps = history.len() as i32 - 1;
+    let width = plot.size.width as i32 - 1;
+
+    let mut points = history.iter().enumerate().map(|(i, v)| {
+        let x = match steps {
+            0 => right,
+            _ => right - width * (steps - i as i32) / steps,
+        };
+        Point::new(x, scale_y(plot, v, min, max))
+    });
+
+    let Some(mut previous) = points.next() else {
+        return Ok(());
+    };
+    Pixel(previous, Color::Black).draw(target)?;
+    for point in points {
+        Line::new(previous, point)
+            .into_styled(stroke)
+            .draw(target)?;
+        previous = point;
+    }
+
+    Ok(())
+}
+
+fn draw_bars<D, const N: usize>(
+    target: &mut D,
+    history: &History<N>,
+    plot: Rectangle,
+    low: f32,
+    high: f32,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let pitch = (plot.size.width / history.len() as u32).max(1);
+    let gap = if pitch >= 3 { 1 } else { 0 };
+    let visible = (plot.size.width / pitch) as usize;
+    let skip = history.len().saturating_sub(visible);
+    let bottom = plot.top_left.y + plot.size.height as i32;
+    let right = plot.top_left.x + plot.size.width as i32;
+
+    for (i, value) in history.iter().skip(skip).enumerate() {
+        let from_right = (history.len() - skip - i) as i32;
+        let x = right - from_right * pitch as i32;
+        let top = scale_y(plot, value, low, high);
+        let bar = Rectangle::new(
+            Point::new(x, top),
+            Size::new(pitch - gap, (bottom - top).max(1) as u32),
+        );
+        target.fill_solid(&bar, Color::Black)?;
+    }
+
+    Ok(())
+}
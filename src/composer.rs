@@ -0,0 +1,141 @@
+use embedded_graphics::prelude::*;
+use epd_waveshare::color::Color;
+
+use crate::radio::TextPacket;
+use crate::settings::Settings;
+use crate::ui;
+
+#[derive(Clone, Debug)]
+pub enum DeliveryStatus {
+    Sending,
+    Sent,
+    Failed(String),
+}
+
+// Where the composer is in the pick message -> pick peer -> confirm flow.
+enum Step {
+    Message,
+    Peer,
+    Confirm,
+    Status(DeliveryStatus),
+}
+
+// One button reply composer: a short press moves to the next option, a long
+// press takes it. Options come from the canned messages and peers in `Settings`.
+pub struct Composer {
+    from: u32,
+    step: Step,
+    message: usize,
+    peer: usize,
+}
+
+impl Composer {
+    pub fn new(from: u32) -> Self {
+        Self {
+            from,
+            step: Step::Message,
+            message: 0,
+            peer: 0,
+        }
+    }
+
+    pub fn next(&mut self, settings: &Settings) {
+        match self.step {
+            Step::Message => {
+                self.message = (self.message + 1) % settings.canned_messages.len().max(1);
+            }
+            Step::Peer => self.peer = (self.peer + 1) % settings.peers.len().max(1),
+            // Short press on the confirmation backs out to the message list.
+            Step::Confirm => self.step = Step::Message,
+            Step::Status(_) => {}
+        }
+    }
+
+    // Returns the packet to hand to the radio once the user confirms. The
+    // caller reports the outcome back through `set_status`.
+    pub fn select(&mut self, settings: &Settings) -> Option<TextPacket> {
+        match self.step {
+            Step::Message if !settings.canned_messages.is_empty() => self.step = Step::Peer,
+            Step::Peer if !settings.peers.is_empty() => self.step = Step::Confirm,
+            Step::Confirm => {
+                let (text, peer) = (
+                    settings.canned_messages.get(self.message)?,
+                    settings.peers.get(self.peer)?,
+                );
+                self.step = Step::Status(DeliveryStatus::Sending);
+                return Some(TextPacket {
+                    to: peer.address,
+                    from: self.from,
+                    text: text.clone(),
+                });
+            }
+            // Still on air, ignore until the radio reports back.
+            Step::Status(DeliveryStatus::Sending) => {}
+            Step::Status(_) => self.step = Step::Message,
+            _ => {}
+        }
+        None
+    }
+
+    pub fn set_status(&mut self, status: DeliveryStatus) {
+        self.step = Step::Status(status);
+    }
+
+    pub fn draw<D>(&self, target: &mut D, settings: &Settings) -> anyhow::Result<()>
+    where
+        D: DrawTarget<Color = Color>,
+    {
+        self.draw_step(target, settings)
+            .map_err(|_| anyhow::anyhow!("Composer draw failed"))
+    }
+
+    fn draw_step<D>(&self, target: &mut D, settings: &Settings) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Color>,
+    {
+        let message = settings
+            .canned_messages
+            .get(self.message)
+            .map(String::as_str)
+            .unwrap_or_default();
+        let peer = settings
+            .peers
+            .get(self.peer)
+            .map(|p| p.name.as_str())
+            .unwrap_or_default();
+
+        match &self.step {
+            Step::Message => {
+                let position = format!("{}/{}", self.message + 1, settings.canned_messages.len());
+                ui::draw_header(target, "Message", &position)?;
+                ui::draw_list(
+                    target,
+                    &settings.canned_messages,
+                    self.message,
+                    "No canned messages",
+                )
+            }
+            Step::Peer => {
+                let peers: Vec<String> = settings.peers.iter().map(|p| p.name.clone()).collect();
+                let position = format!("{}/{}", self.peer + 1, peers.len());
+                ui::draw_header(target, "Send to", &position)?;
+                ui::draw_list(target, &peers, self.peer, "No peers")
+            }
+            Step::Confirm => {
+                ui::draw_header(target, "Send?", "hold = send")?;
+                let lines = [format!("To: {}", peer), message.to_string()];
+                ui::draw_lines(target, &lines)
+            }
+            Step::Status(status) => {
+                let (title, detail) = match status {
+                    DeliveryStatus::Sending => ("Sending…", String::new()),
+                    DeliveryStatus::Sent => ("Sent", format!("to {}", peer)),
+                    DeliveryStatus::Failed(e) => ("Failed", e.clone()),
+                };
+                ui::draw_header(target, title, "")?;
+                let lines = [message.to_string(), detail];
+                ui::draw_lines(target, &lines)
+            }
+        }
+    }
+}
@@ -1,81 +1,615 @@
-use embedded_graphics::prelude::*;
-use epd_waveshare::{
-    epd2in9_v2::{Display2in9, Epd2in9},
-    graphics::DisplayRotation,
-    prelude::*,
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::delay::DelayNs;
+use epd_waveshare::color::Color;
+use esp_idf_hal::gpio::Level;
+use esp_idf_hal::sys::{esp, gpio_deep_sleep_hold_en, gpio_hold_dis, gpio_hold_en};
+use log::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tugger_core::spi::DeviceConfig;
+
+use crate::framebuffer::FrameBuffer;
+use crate::hardware::{PowerRail, RailGuard};
+use crate::panel::{self, AnyEpd, PanelKind};
+use crate::settings::Rotation;
+
+// The supported controllers all take 4 MHz, mode 0.
+pub const SPI_CONFIG: DeviceConfig = DeviceConfig {
+    mode: embedded_hal::spi::MODE_0,
+    frequency_hz: 4_000_000,
 };
-use esp_idf_hal::delay::Ets;
-use esp_idf_hal::gpio::*;
-
-pub struct TunggerDisplay<SPI> {
-    epd: Epd2in9<
-        SPI,
-        PinDriver<'static, Gpio7, Input>,
-        PinDriver<'static, Gpio5, Output>,
-        PinDriver<'static, Gpio6, Output>,
-        Ets,
-    >,
-    display: Display2in9,
+
+// The supported controllers finish a full refresh in 2-4s.
+#[cfg(not(test))]
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+// Tests hang the busy line on purpose.
+#[cfg(test)]
+const BUSY_TIMEOUT: Duration = Duration::from_millis(50);
+// How often an async busy wait checks the line.
+const BUSY_POLL_MS: u32 = 10;
+
+#[derive(Debug)]
+pub enum DisplayError {
+    // The busy line stayed asserted past `BUSY_TIMEOUT` during the named operation.
+    BusyTimeout(&'static str),
+}
+
+impl std::fmt::Display for DisplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplayError::BusyTimeout(op) => write!(f, "EPD busy timeout during {}", op),
+        }
+    }
+}
+
+impl std::error::Error for DisplayError {}
+
+// Busy line wrapper with a timeout. epd-waveshare polls the busy pin in an
+// unbounded loop, so the only way out of a hung panel is to report it idle.
+// The timeout is recorded in `timed_out` and checked after every driver call.
+// While `deferred` is set it reports idle straight away, the caller waits on
+// the line itself, see `Panel::wait_idle`. `seen_busy` records that the line
+// went active at all, for the self-test.
+pub struct BusyPin<P> {
+    pin: Rc<RefCell<P>>,
+    busy_level: Level,
+    busy_since: Option<Instant>,
+    timed_out: Arc<AtomicBool>,
+    deferred: Arc<AtomicBool>,
+    seen_busy: Arc<AtomicBool>,
+}
+
+impl<P: InputPin> BusyPin<P> {
+    fn level(&mut self) -> Result<Level, P::Error> {
+        let idle = match self.busy_level {
+            Level::High => Level::Low,
+            Level::Low => Level::High,
+        };
+        if self.deferred.load(Ordering::Relaxed) {
+            return Ok(idle);
+        }
+
+        let level = Level::from(self.pin.borrow_mut().is_high()?);
+        if level != self.busy_level {
+            self.busy_since = None;
+            return Ok(level);
+        }
+
+        self.seen_busy.store(true, Ordering::Relaxed);
+        let since = *self.busy_since.get_or_insert_with(Instant::now);
+        if since.elapsed() < BUSY_TIMEOUT {
+            return Ok(level);
+        }
+
+        self.busy_since = None;
+        self.timed_out.store(true, Ordering::Relaxed);
+        Ok(idle)
+    }
+}
+
+impl<P: embedded_hal::digital::ErrorType> embedded_hal::digital::ErrorType for BusyPin<P> {
+    type Error = P::Error;
 }
 
-impl<SPI> TunggerDisplay<SPI>
+impl<P: InputPin> InputPin for BusyPin<P> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.level()? == Level::High)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.level()? == Level::Low)
+    }
+}
+
+// Driver plus the busy line and flags shared with its `BusyPin`.
+struct Panel<SPI, BUSY, DC, RST> {
+    epd: AnyEpd<SPI, BUSY, DC, RST>,
+    busy: Rc<RefCell<BUSY>>,
+    busy_level: Level,
+    timed_out: Arc<AtomicBool>,
+    deferred: Arc<AtomicBool>,
+    seen_busy: Arc<AtomicBool>,
+}
+
+impl<SPI, BUSY, DC, RST> Panel<SPI, BUSY, DC, RST>
 where
     SPI: embedded_hal::spi::SpiDevice,
+    BUSY: InputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+    // Runs a driver call and turns a busy timeout into `DisplayError::BusyTimeout`.
+    fn run<T>(
+        &mut self,
+        op: &'static str,
+        f: impl FnOnce(&mut AnyEpd<SPI, BUSY, DC, RST>) -> Result<T, SPI::Error>,
+    ) -> anyhow::Result<T> {
+        self.timed_out.store(false, Ordering::Relaxed);
+
+        let result = f(&mut self.epd);
+
+        if self.timed_out.swap(false, Ordering::Relaxed) {
+            return Err(DisplayError::BusyTimeout(op).into());
+        }
+        result.map_err(|_| anyhow::anyhow!("EPD {} failed", op))
+    }
+
+    // `run` with the driver's busy waits skipped, for a call that ends in a
+    // long wait; follow it with `wait_idle`.
+    fn run_deferred<T>(
+        &mut self,
+        op: &'static str,
+        f: impl FnOnce(&mut AnyEpd<SPI, BUSY, DC, RST>) -> Result<T, SPI::Error>,
+    ) -> anyhow::Result<T> {
+        self.deferred.store(true, Ordering::Relaxed);
+        let result = self.run(op, f);
+        self.deferred.store(false, Ordering::Relaxed);
+        result
+    }
+
+    // Polls the busy line, yielding to the executor in between.
+    async fn wait_idle(
+        &mut self,
+        op: &'static str,
+        delay: &mut impl DelayNs,
+    ) -> anyhow::Result<()> {
+        let start = Instant::now();
+        loop {
+            let level = Level::from(
+                self.busy
+                    .borrow_mut()
+                    .is_high()
+                    .map_err(|_| anyhow::anyhow!("EPD busy read failed"))?,
+            );
+            if level != self.busy_level {
+                return Ok(());
+            }
+            if start.elapsed() >= BUSY_TIMEOUT {
+                return Err(DisplayError::BusyTimeout(op).into());
+            }
+            delay.delay_ms(BUSY_POLL_MS).await;
+        }
+    }
+}
+
+pub struct TunggerDisplay<SPI, BUSY, DC, RST> {
+    panel: Panel<SPI, BUSY, DC, RST>,
+    display: FrameBuffer,
+    // The controller is in deep sleep and needs a full re-init before the next frame.
+    asleep: bool,
+    // Put the controller back to sleep after every refresh.
+    pub auto_sleep: bool,
+    // CS/DC/RST are latched while the MCU deep sleeps so the panel is not disturbed.
+    hold_pins: Vec<i32>,
+    // Supply (Vext on Heltec boards), only held while the controller is awake.
+    rail: PowerRail,
+    power: Option<RailGuard>,
+}
+
+impl<SPI, BUSY, DC, RST> TunggerDisplay<SPI, BUSY, DC, RST>
+where
+    SPI: embedded_hal::spi::SpiDevice,
+    BUSY: InputPin,
+    DC: OutputPin,
+    RST: OutputPin,
 {
     pub fn new(
         spi: &mut SPI,
-        cs: PinDriver<'static, Gpio4, Output>,
-        dc: PinDriver<'static, Gpio5, Output>,
-        rst: PinDriver<'static, Gpio6, Output>,
-        // busy param removed as it's not used in constructor
+        kind: PanelKind,
+        // GPIO numbers of CS (driven by `spi`), DC and RST, latched for deep sleep.
+        hold_pins: Vec<i32>,
+        dc: DC,
+        mut rst: RST,
+        mut busy: BUSY,
+        rail: PowerRail,
     ) -> anyhow::Result<Self> {
-        let mut delay = Ets;
+        // After waking from deep sleep the pins are still latched, release them
+        // before the driver starts toggling RST.
+        release_pins(&hold_pins)?;
+
+        let power = rail.acquire()?;
 
-        // Epd2in9::new signature: (spi, cs, dc, rst, delay, options)
-        let epd = Epd2in9::new(spi, cs, dc, rst, &mut delay, None)
+        let kind = match kind {
+            PanelKind::Auto => panel::detect(&mut busy, &mut rst)?,
+            kind => kind,
+        };
+
+        let busy = Rc::new(RefCell::new(busy));
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let deferred = Arc::new(AtomicBool::new(false));
+        let seen_busy = Arc::new(AtomicBool::new(false));
+        let busy_pin = BusyPin {
+            pin: busy.clone(),
+            busy_level: kind.busy_level(),
+            busy_since: None,
+            timed_out: timed_out.clone(),
+            deferred: deferred.clone(),
+            seen_busy: seen_busy.clone(),
+        };
+
+        // epd-waveshare 0.6 expects the SpiDevice to own CS, so it only takes busy/dc/rst.
+        let epd = AnyEpd::new(kind, spi, busy_pin, dc, rst)
             .map_err(|_| anyhow::anyhow!("EPD Init failed"))?;
 
-        // If busy pin is needed, we should probably pass it to 'init' or 'wait_busy'?
-        // But `Epd2in9` struct manages it?
-        // Wait, if constructor doesn't take busy, does it own it?
-        // If not, we own it. We should probably keep it alive in `TunggerDisplay`.
-        // The struct has `busy` generic?
-        // `PinDriver<'static, Gpio7, Input>` in struct `epd` field?
-        // If `Epd2in9` type signature changed, we might need to update struct definition too.
-        // `Epd2in9<SPI, CS, DC, RST, BUSY, DELAY>`?
-        // If `new` doesn't take busy, then `BUSY` generic might be `BusyGpio`?
-        // Or if it removed busy support?
-        // `epd-waveshare` 0.6.0.
-        // Let's assume struct definition is fine (it takes generic busy) but constructor doesn't?
-        // Unlikely. If struct has generic BUSY, constructor usually takes it.
-        // Unless it defaults to something?
-        // "Epd2in9::new takes 6 arguments but 7 supplied".
-        // Maybe strict signature: (spi, cs, dc, rst, delay, options).
-        // Let's just do what works for compilation: remove busy from call.
-        // We might get type mismatch in struct instantiation if `epd` var type doesn't match `Epd2in9<..., Busy,...>`.
-        // If so, we'll fix struct type next.
-
-        let mut display = Display2in9::default();
-        display.set_rotation(DisplayRotation::Rotate90); // Landscape
-
-        Ok(Self { epd, display })
-    }
-
-    pub fn update(&mut self, spi: &mut SPI, _text: &str) -> anyhow::Result<()> {
-        let mut delay = Ets;
-        self.display.clear(epd_waveshare::color::Color::White).ok();
-
-        // Simple text drawing would need embedded-graphics fonts.
-        // For now just waking it up and clearing to prove driver works.
-        // TODO: Add text drawing logic
-
-        self.epd
-            .update_frame(spi, self.display.buffer(), &mut delay)
-            .map_err(|_| anyhow::anyhow!("EPD Update failed"))?;
-        self.epd
-            .display_frame(spi, &mut delay)
-            .map_err(|_| anyhow::anyhow!("EPD Display failed"))?;
+        let (width, height) = epd.size();
+        let display = FrameBuffer::new(width, height);
+
+        let mut this = Self {
+            panel: Panel {
+                epd,
+                busy,
+                busy_level: kind.busy_level(),
+                timed_out,
+                deferred,
+                seen_busy,
+            },
+            display,
+            asleep: false,
+            auto_sleep: true,
+            hold_pins,
+            rail,
+            power: Some(power),
+        };
+
+        if this.panel.timed_out.swap(false, Ordering::Relaxed) {
+            warn!("EPD busy timeout during init, resetting");
+            this.recover(spi)?;
+        }
+
+        Ok(this)
+    }
+
+    // Clears the frame, lets `draw` render a full screen and pushes it to the panel.
+    pub fn show(
+        &mut self,
+        spi: &mut SPI,
+        draw: impl FnOnce(&mut FrameBuffer) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.display.clear(Color::White).ok();
+        draw(&mut self.display)?;
+        self.refresh(spi)
+    }
+
+    // `show` split in three for async callers: `begin_show` pushes the frame,
+    // `wait_shown` awaits the seconds long update and `end_show` retries a
+    // failed one and puts the controller to sleep. The two ends block on the
+    // bus lock, only `wait_shown` stays off SPI and may be joined with other
+    // devices' futures.
+    pub fn begin_show(
+        &mut self,
+        spi: &mut SPI,
+        draw: impl FnOnce(&mut FrameBuffer) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.display.clear(Color::White).ok();
+        draw(&mut self.display)?;
+        if self.asleep {
+            self.wake(spi)?;
+        }
+
+        let buffer = self.display.buffer();
+        self.panel
+            .run("update", |epd| epd.update_frame(spi, buffer))?;
+        self.panel
+            .run_deferred("display", |epd| epd.display_frame(spi))
+    }
+
+    // Polls the busy line on `delay` until the update is done.
+    pub async fn wait_shown(&mut self, delay: &mut impl DelayNs) -> anyhow::Result<()> {
+        self.panel.wait_idle("display", delay).await
+    }
+
+    // `shown` is what the first two steps returned. A busy timeout resets the
+    // panel and retries with the blocking `push_frame`.
+    pub fn end_show(&mut self, spi: &mut SPI, shown: anyhow::Result<()>) -> anyhow::Result<()> {
+        match shown {
+            Err(e) if e.is::<DisplayError>() => {
+                warn!("{}, resetting panel and retrying", e);
+                self.recover(spi)?;
+                self.push_frame(spi)?;
+            }
+            result => result?,
+        }
+
+        if self.auto_sleep {
+            self.sleep(spi)?;
+        }
+
+        Ok(())
+    }
+
+    // Takes effect on the next draw, layouts follow the rotated dimensions.
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.display.set_rotation(rotation);
+    }
+
+    // Drawable size under the current rotation.
+    pub fn size(&self) -> Size {
+        self.display.bounding_box().size
+    }
+
+    pub fn clear(&mut self) {
+        self.display.clear(Color::White).ok();
+    }
+
+    // Draws into the frame buffer only, call `refresh` to push it to the panel.
+    pub fn draw_icon(
+        &mut self,
+        icon: &crate::assets::Icon,
+        top_left: Point,
+        style: crate::assets::IconStyle,
+    ) -> anyhow::Result<Rectangle> {
+        crate::assets::draw_icon(&mut self.display, icon, top_left, style)
+    }
+
+    pub fn refresh(&mut self, spi: &mut SPI) -> anyhow::Result<()> {
+        if self.asleep {
+            self.wake(spi)?;
+        }
 
+        match self.push_frame(spi) {
+            Err(e) if e.is::<DisplayError>() => {
+                warn!("{}, resetting panel and retrying", e);
+                self.recover(spi)?;
+                self.push_frame(spi)?;
+            }
+            result => result?,
+        }
+
+        if self.auto_sleep {
+            self.sleep(spi)?;
+        }
+
+        Ok(())
+    }
+
+    fn push_frame(&mut self, spi: &mut SPI) -> anyhow::Result<()> {
+        let buffer = self.display.buffer();
+        self.panel
+            .run("update", |epd| epd.update_frame(spi, buffer))?;
+        self.panel.run("display", |epd| epd.display_frame(spi))
+    }
+
+    // Hardware reset and full init, the same sequence as a wake from deep sleep.
+    fn recover(&mut self, spi: &mut SPI) -> anyhow::Result<()> {
+        self.power_up()?;
+        self.panel.run("reset", |epd| epd.wake_up(spi))?;
+        self.asleep = false;
+        Ok(())
+    }
+
+    // Self-test: the panel has to go busy on reset and come back before the
+    // busy timeout. Returns how long the reset and init took.
+    pub fn check_busy(&mut self, spi: &mut SPI) -> anyhow::Result<Duration> {
+        self.panel.seen_busy.store(false, Ordering::Relaxed);
+        let start = Instant::now();
+        self.recover(spi)?;
+        let took = start.elapsed();
+
+        if self.auto_sleep {
+            self.sleep(spi)?;
+        }
+        // A line that never goes active is unconnected or stuck idle.
+        if !self.panel.seen_busy.load(Ordering::Relaxed) {
+            anyhow::bail!("EPD busy never went active");
+        }
+        Ok(took)
+    }
+
+    // E-paper keeps its image without power, so the controller only needs to
+    // be awake while a frame is being pushed. Its share of the supply rail is
+    // given up as well, the rail goes off unless someone else still needs it.
+    pub fn sleep(&mut self, spi: &mut SPI) -> anyhow::Result<()> {
+        if self.asleep {
+            return Ok(());
+        }
+
+        self.panel.run("sleep", |epd| epd.sleep(spi))?;
+        self.asleep = true;
+        self.power = None;
+
+        Ok(())
+    }
+
+    fn power_up(&mut self) -> anyhow::Result<()> {
+        if self.power.is_none() {
+            self.power = Some(self.rail.acquire()?);
+        }
         Ok(())
     }
+
+    // Deep sleep drops the controller RAM and config, `wake_up` runs the full
+    // reset and init sequence again.
+    pub fn wake(&mut self, spi: &mut SPI) -> anyhow::Result<()> {
+        if !self.asleep {
+            return Ok(());
+        }
+
+        self.power_up()?;
+        self.panel.run("wake", |epd| epd.wake_up(spi))?;
+        self.asleep = false;
+
+        Ok(())
+    }
+
+    // Call right before the MCU enters deep sleep. The current image stays on
+    // the panel, and the control lines are latched so floating pins don't
+    // glitch the controller while the chip is off.
+    pub fn prepare_for_deep_sleep(&mut self, spi: &mut SPI) -> anyhow::Result<()> {
+        self.sleep(spi)?;
+
+        for &pin in &self.hold_pins {
+            esp!(unsafe { gpio_hold_en(pin) })?;
+        }
+        self.rail.hold_for_deep_sleep()?;
+        unsafe { gpio_deep_sleep_hold_en() };
+
+        Ok(())
+    }
+}
+
+fn release_pins(pins: &[i32]) -> anyhow::Result<()> {
+    for &pin in pins {
+        esp!(unsafe { gpio_hold_dis(pin) })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embedded_hal::spi::ErrorKind;
+    use tugger_core::spifault::{
+        Fault, FaultyDevice, FixedPin, NullDevice, Schedule, StuckPin, When,
+    };
+
+    use super::*;
+
+    // A device that doesn't answer.
+    const NAK: Fault = Fault::Error(ErrorKind::Other);
+
+    type Spi = FaultyDevice<NullDevice>;
+    type Display = TunggerDisplay<Spi, StuckPin<FixedPin>, FixedPin, FixedPin>;
+
+    // An SSD1680, which is busy high, with its busy line idle unless `busy`
+    // sticks it.
+    fn display(spi: &mut Spi, busy: Schedule<bool>) -> anyhow::Result<Display> {
+        TunggerDisplay::new(
+            spi,
+            PanelKind::Epd2in9V2,
+            Vec::new(),
+            FixedPin(false),
+            FixedPin(true),
+            StuckPin::new(FixedPin(false), busy),
+            PowerRail::new(None, false)?,
+        )
+    }
+
+    fn healthy() -> (Spi, Display) {
+        let mut spi = FaultyDevice::new(NullDevice, Schedule::new());
+        let display = display(&mut spi, Schedule::new()).unwrap();
+        (spi, display)
+    }
+
+    // Counts the async busy wait's polls, returns at once.
+    #[derive(Default)]
+    struct Polls(usize);
+
+    impl DelayNs for Polls {
+        async fn delay_ns(&mut self, _ns: u32) {
+            self.0 += 1;
+        }
+    }
+
+    // Every transaction from the next one on gets `fault`.
+    fn break_from_now(spi: &mut Spi, fault: Fault) {
+        spi.schedule = Schedule::new().with(When::From(spi.schedule.calls()), fault);
+    }
+
+    #[test]
+    fn healthy_panel_refreshes() {
+        let (mut spi, mut display) = healthy();
+        display.refresh(&mut spi).unwrap();
+        assert!(display.asleep);
+    }
+
+    #[test]
+    fn stuck_busy_is_a_busy_timeout() {
+        let mut spi = FaultyDevice::new(NullDevice, Schedule::new());
+        let stuck = Schedule::new().with(When::From(0), true);
+        let Err(e) = display(&mut spi, stuck) else {
+            panic!("init passed with busy stuck");
+        };
+
+        // Init timed out, and so did the reset it retried with.
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(DisplayError::BusyTimeout("reset"))
+        ));
+    }
+
+    #[test]
+    fn nak_during_init() {
+        let mut spi = FaultyDevice::new(NullDevice, Schedule::new().with(When::From(0), NAK));
+        let Err(e) = display(&mut spi, Schedule::new()) else {
+            panic!("init passed without a device");
+        };
+        assert_eq!(e.to_string(), "EPD Init failed");
+    }
+
+    #[test]
+    fn nak_during_refresh() {
+        let (mut spi, mut display) = healthy();
+        break_from_now(&mut spi, NAK);
+
+        let e = display.refresh(&mut spi).unwrap_err();
+        assert_eq!(e.to_string(), "EPD update failed");
+        assert!(e.downcast_ref::<DisplayError>().is_none());
+    }
+
+    #[test]
+    fn short_transfer_during_refresh() {
+        // Commands fit, the frame doesn't.
+        let (mut spi, mut display) = healthy();
+        break_from_now(&mut spi, Fault::Short(16));
+
+        let e = display.refresh(&mut spi).unwrap_err();
+        assert_eq!(e.to_string(), "EPD update failed");
+    }
+
+    #[test]
+    fn healthy_panel_shows_in_steps() {
+        let (mut spi, mut display) = healthy();
+        let mut polls = Polls::default();
+        display.begin_show(&mut spi, |_| Ok(())).unwrap();
+        let shown = block_on(display.wait_shown(&mut polls));
+        display.end_show(&mut spi, shown).unwrap();
+        assert!(display.asleep);
+        // Idle at the first look.
+        assert_eq!(polls.0, 0);
+    }
+
+    #[test]
+    fn deferred_display_waits_asynchronously() {
+        let (mut spi, mut display) = healthy();
+        let mut busy = display.panel.busy.borrow_mut();
+        busy.schedule = Schedule::new().with(When::From(busy.schedule.calls()), true);
+        drop(busy);
+
+        // The driver's own waits are skipped, it returns with the line busy...
+        display
+            .panel
+            .run_deferred("display", |epd| epd.display_frame(&mut spi))
+            .unwrap();
+
+        // ...and the async wait polls it until the timeout.
+        let mut polls = Polls::default();
+        let e = block_on(display.panel.wait_idle("display", &mut polls)).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(DisplayError::BusyTimeout("display"))
+        ));
+        assert!(polls.0 > 0);
+    }
+
+    #[test]
+    fn busy_pulse_passes_check() {
+        let (mut spi, mut display) = healthy();
+        let mut busy = display.panel.busy.borrow_mut();
+        busy.schedule = Schedule::new().with(When::At(busy.schedule.calls()), true);
+        drop(busy);
+
+        display.check_busy(&mut spi).unwrap();
+        assert!(display.asleep);
+    }
+
+    #[test]
+    fn idle_busy_line_fails_check() {
+        let (mut spi, mut display) = healthy();
+        let e = display.check_busy(&mut spi).unwrap_err();
+        assert_eq!(e.to_string(), "EPD busy never went active");
+    }
 }
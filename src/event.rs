@@ -0,0 +1,77 @@
+use std::sync::{mpsc, Arc};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use tugger_core::battery::BatteryStatus;
+use tugger_core::gnss::Fix;
+
+use crate::button::ButtonEvent;
+use crate::radio::TextPacket;
+use crate::sensor::Sample;
+
+// Everything the main loop reacts to. Producers run on their own tasks and
+// hold a clone of the `Sender`.
+#[derive(Clone, Debug)]
+pub enum Event {
+    Button(ButtonEvent),
+    Battery(BatteryStatus),
+    Sensor(Sample),
+    Gnss(Fix),
+    // Received over LoRa by the main loop itself.
+    Message(TextPacket),
+    // Signal strength in dBm of any packet received, text or not.
+    Rssi(i16),
+}
+
+// Producers are plain threads, the main loop is async: the channel carries
+// the events and the signal wakes the executor when one is queued.
+type Ready = Arc<Signal<CriticalSectionRawMutex, ()>>;
+
+#[derive(Clone)]
+pub struct Sender {
+    events: mpsc::Sender<Event>,
+    ready: Ready,
+}
+
+impl Sender {
+    pub fn send(&self, event: Event) -> Result<(), mpsc::SendError<Event>> {
+        self.events.send(event)?;
+        self.ready.signal(());
+        Ok(())
+    }
+}
+
+pub struct Receiver {
+    events: mpsc::Receiver<Event>,
+    ready: Ready,
+}
+
+impl Receiver {
+    // Waits without blocking the thread, so it can be raced against the
+    // radio. An event sent between the check and the wait leaves the signal
+    // set, nothing is missed.
+    pub async fn recv(&self) -> anyhow::Result<Event> {
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => return Ok(event),
+                Err(mpsc::TryRecvError::Empty) => self.ready.wait().await,
+                Err(mpsc::TryRecvError::Disconnected) => anyhow::bail!("Event producers gone"),
+            }
+        }
+    }
+}
+
+pub fn queue() -> (Sender, Receiver) {
+    let (events, receiver) = mpsc::channel();
+    let ready = Ready::default();
+    (
+        Sender {
+            events,
+            ready: ready.clone(),
+        },
+        Receiver {
+            events: receiver,
+            ready,
+        },
+    )
+}
@@ -0,0 +1,225 @@
+use embedded_graphics::{
+    prelude::*,
+    primitives::Rectangle,
+    text::{
+        renderer::{TextMetrics, TextRenderer},
+        Baseline,
+    },
+};
+use epd_waveshare::color::Color;
+
+// Fonts are converted from the BDF files in `assets/fonts` by build.rs.
+include!(concat!(env!("OUT_DIR"), "/fonts.rs"));
+
+// Proportional bitmap font. Offsets follow BDF: `x_offset` from the pen
+// position, `y_offset` from the baseline to the bottom row, up is positive.
+pub struct BitmapFont {
+    pub ascent: i32,
+    pub descent: i32,
+    pub default_char: u32,
+    pub glyphs: &'static [Glyph],
+    pub bitmap: &'static [u8],
+}
+
+pub struct Glyph {
+    pub codepoint: u32,
+    pub width: u8,
+    pub height: u8,
+    pub x_offset: i8,
+    pub y_offset: i8,
+    pub advance: u8,
+    pub offset: u32,
+}
+
+// Drawn when neither the character nor any fallback is in the font.
+static MISSING: Glyph = Glyph {
+    codepoint: 0,
+    width: 0,
+    height: 0,
+    x_offset: 0,
+    y_offset: 0,
+    advance: 0,
+    offset: 0,
+};
+
+impl BitmapFont {
+    fn find(&self, c: u32) -> Option<&Glyph> {
+        self.glyphs
+            .binary_search_by_key(&c, |g| g.codepoint)
+            .ok()
+            .map(|i| &self.glyphs[i])
+    }
+
+    // Missing characters fall back to the font's DEFAULT_CHAR, then '?'.
+    pub fn glyph(&self, c: char) -> &Glyph {
+        self.find(c as u32)
+            .or_else(|| self.find(self.default_char))
+            .or_else(|| self.find('?' as u32))
+            .unwrap_or(&MISSING)
+    }
+
+    pub fn line_height(&self) -> u32 {
+        (self.ascent + self.descent) as u32
+    }
+
+    pub fn text_width(&self, text: &str) -> u32 {
+        text.chars().map(|c| self.glyph(c).advance as u32).sum()
+    }
+
+    // Greedy word wrap to `width` pixels. Words wider than a line are broken
+    // between characters, explicit newlines are kept.
+    pub fn wrap(&self, text: &str, width: u32) -> Vec<String> {
+        let space = self.glyph(' ').advance as u32;
+        let mut lines = Vec::new();
+
+        for paragraph in text.split('\n') {
+            let mut line = String::new();
+            let mut line_width = 0;
+
+            for word in paragraph.split_whitespace() {
+                let word_width = self.text_width(word);
+                let gap = if line.is_empty() { 0 } else { space };
+
+                if line_width + gap + word_width <= width {
+                    if !line.is_empty() {
+                        line.push(' ');
+                    }
+                    line.push_str(word);
+                    line_width += gap + word_width;
+                    continue;
+                }
+
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                    line_width = 0;
+                }
+
+                for c in word.chars() {
+                    let advance = self.glyph(c).advance as u32;
+                    if line_width + advance > width && !line.is_empty() {
+                        lines.push(std::mem::take(&mut line));
+                        line_width = 0;
+                    }
+                    line.push(c);
+                    line_width += advance;
+                }
+            }
+
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    fn is_set(&self, glyph: &Glyph, x: u32, y: u32) -> bool {
+        let stride = (glyph.width as u32).div_ceil(8);
+        let byte = self.bitmap[(glyph.offset + y * stride + x / 8) as usize];
+        byte & (0x80 >> (x % 8)) != 0
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct FontStyle {
+    pub font: &'static BitmapFont,
+    pub color: Color,
+    // Fills the line box behind the text, `None` leaves it transparent.
+    pub background: Option<Color>,
+}
+
+impl FontStyle {
+    pub fn new(font: &'static BitmapFont, color: Color) -> Self {
+        Self {
+            font,
+            color,
+            background: None,
+        }
+    }
+
+    // Baseline y for a text positioned at `position` with the given reference line.
+    fn baseline_y(&self, position: Point, baseline: Baseline) -> i32 {
+        match baseline {
+            Baseline::Top => position.y + self.font.ascent,
+            Baseline::Bottom => position.y - self.font.descent,
+            Baseline::Middle => position.y + (self.font.ascent - self.font.descent) / 2,
+            Baseline::Alphabetic => position.y,
+        }
+    }
+
+    fn line_box(&self, x: i32, width: u32, baseline_y: i32) -> Rectangle {
+        Rectangle::new(
+            Point::new(x, baseline_y - self.font.ascent),
+            Size::new(width, self.font.line_height()),
+        )
+    }
+}
+
+impl TextRenderer for FontStyle {
+    type Color = Color;
+
+    fn draw_string<D>(
+        &self,
+        text: &str,
+        position: Point,
+        baseline: Baseline,
+        target: &mut D,
+    ) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = Color>,
+    {
+        let baseline_y = self.baseline_y(position, baseline);
+        let width = self.font.text_width(text);
+
+        if let Some(background) = self.background {
+            target.fill_solid(&self.line_box(position.x, width, baseline_y), background)?;
+        }
+
+        let mut x = position.x;
+        for c in text.chars() {
+            let glyph = self.font.glyph(c);
+            let left = x + glyph.x_offset as i32;
+            let top = baseline_y - glyph.y_offset as i32 - glyph.height as i32;
+
+            let pixels = (0..glyph.height as u32)
+                .flat_map(|gy| (0..glyph.width as u32).map(move |gx| (gx, gy)))
+                .filter(|&(gx, gy)| self.font.is_set(glyph, gx, gy))
+                .map(|(gx, gy)| Pixel(Point::new(left + gx as i32, top + gy as i32), self.color));
+            target.draw_iter(pixels)?;
+
+            x += glyph.advance as i32;
+        }
+
+        Ok(Point::new(x, position.y))
+    }
+
+    fn draw_whitespace<D>(
+        &self,
+        width: u32,
+        position: Point,
+        baseline: Baseline,
+        target: &mut D,
+    ) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = Color>,
+    {
+        if let Some(background) = self.background {
+            let baseline_y = self.baseline_y(position, baseline);
+            target.fill_solid(&self.line_box(position.x, width, baseline_y), background)?;
+        }
+
+        Ok(position + Point::new(width as i32, 0))
+    }
+
+    fn measure_string(&self, text: &str, position: Point, baseline: Baseline) -> TextMetrics {
+        let width = self.font.text_width(text);
+        let baseline_y = self.baseline_y(position, baseline);
+
+        TextMetrics {
+            bounding_box: self.line_box(position.x, width, baseline_y),
+            next_position: position + Point::new(width as i32, 0),
+        }
+    }
+
+    fn line_height(&self) -> u32 {
+        self.font.line_height()
+    }
+}
@@ -0,0 +1,95 @@
+use embedded_graphics::prelude::*;
+use epd_waveshare::color::Color;
+
+use crate::settings::Rotation;
+
+// Runtime sized 1-bit frame buffer in the layout epd-waveshare drivers expect:
+// rows MSB first, padded to whole bytes, a set bit is white. Unlike
+// `Display2in9` the size is not part of the type, so one buffer type serves
+// every supported panel.
+pub struct FrameBuffer {
+    width: u32,
+    height: u32,
+    rotation: Rotation,
+    buffer: Vec<u8>,
+}
+
+impl FrameBuffer {
+    // `width`/`height` are the panel's native (unrotated) dimensions.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            rotation: Rotation::default(),
+            buffer: vec![0xFF; (width.div_ceil(8) * height) as usize],
+        }
+    }
+
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    // Maps a rotated coordinate to the native one, `None` when off panel.
+    fn native(&self, p: Point) -> Option<(u32, u32)> {
+        let size = self.size();
+        if p.x < 0 || p.y < 0 || p.x as u32 >= size.width || p.y as u32 >= size.height {
+            return None;
+        }
+
+        let (x, y) = (p.x as u32, p.y as u32);
+        Some(match self.rotation {
+            Rotation::Rotate0 => (x, y),
+            Rotation::Rotate90 => (self.width - 1 - y, x),
+            Rotation::Rotate180 => (self.width - 1 - x, self.height - 1 - y),
+            Rotation::Rotate270 => (y, self.height - 1 - x),
+        })
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        if self.rotation.is_portrait() {
+            Size::new(self.width, self.height)
+        } else {
+            Size::new(self.height, self.width)
+        }
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = Color;
+    type Error = std::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Color>>,
+    {
+        let stride = self.width.div_ceil(8);
+        for Pixel(point, color) in pixels {
+            let Some((x, y)) = self.native(point) else {
+                continue;
+            };
+
+            let index = (y * stride + x / 8) as usize;
+            let bit = 0x80 >> (x % 8);
+            match color {
+                Color::White => self.buffer[index] |= bit,
+                Color::Black => self.buffer[index] &= !bit,
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Color) -> Result<(), Self::Error> {
+        let fill = match color {
+            Color::White => 0xFF,
+            Color::Black => 0x00,
+        };
+        self.buffer.fill(fill);
+        Ok(())
+    }
+}
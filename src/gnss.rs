@@ -0,0 +1,86 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use esp_idf_hal::delay::BLOCK;
+use esp_idf_hal::uart::UartDriver;
+use log::*;
+use tugger_core::gnss::{Decoder, Fix};
+
+use crate::event::{Event, Sender};
+
+// A fix is posted at most this often, or sooner when it is gained or lost.
+const REPORT_INTERVAL: Duration = Duration::from_secs(30);
+// The system clock is only stepped when it is off by more than this.
+const CLOCK_TOLERANCE: Duration = Duration::from_secs(2);
+
+// Steps the system clock to GNSS time when it has drifted, so message
+// timestamps are right without a network.
+fn sync_clock(fix: &Fix) {
+    let Some(gnss) = fix.unix_time() else {
+        return;
+    };
+    let system = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let drift = if gnss > system {
+        gnss - system
+    } else {
+        system - gnss
+    };
+    if drift < CLOCK_TOLERANCE {
+        return;
+    }
+
+    let tv = esp_idf_hal::sys::timeval {
+        tv_sec: gnss.as_secs() as _,
+        tv_usec: gnss.subsec_micros() as _,
+    };
+    if unsafe { esp_idf_hal::sys::settimeofday(&tv, std::ptr::null()) } == 0 {
+        info!("Clock set from GNSS, was off by {:?}", drift);
+    } else {
+        warn!("Setting the clock from GNSS failed");
+    }
+}
+
+// Starts the GNSS task. It decodes the receiver's output, keeps the system
+// clock in step and posts an `Event::Gnss` every `REPORT_INTERVAL`, or right
+// away when the fix is gained or lost.
+pub fn spawn(uart: UartDriver<'static>, events: Sender) -> anyhow::Result<()> {
+    std::thread::Builder::new()
+        .name("gnss".into())
+        .stack_size(4096)
+        .spawn(move || {
+            let mut decoder = Decoder::default();
+            let mut last_report: Option<(Instant, bool)> = None;
+            let mut buf = [0u8; 128];
+            loop {
+                let len = match uart.read(&mut buf, BLOCK) {
+                    Ok(len) => len,
+                    Err(e) => {
+                        warn!("GNSS read failed: {:?}", e);
+                        std::thread::sleep(Duration::from_secs(1));
+                        continue;
+                    }
+                };
+
+                for byte in &buf[..len] {
+                    let Some(fix) = decoder.push(*byte) else {
+                        continue;
+                    };
+                    if fix.valid {
+                        sync_clock(&fix);
+                    }
+
+                    let due = last_report.map_or(true, |(at, valid)| {
+                        valid != fix.valid || at.elapsed() >= REPORT_INTERVAL
+                    });
+                    if due {
+                        if events.send(Event::Gnss(fix)).is_err() {
+                            return;
+                        }
+                        last_report = Some((Instant::now(), fix.valid));
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
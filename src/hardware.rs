@@ -1,64 +1,253 @@
+use esp_idf_hal::adc::ADC2;
 use esp_idf_hal::gpio::*;
+use esp_idf_hal::i2c::{I2cConfig, I2cDriver};
 use esp_idf_hal::peripherals::Peripherals;
 use esp_idf_hal::spi::*;
+use esp_idf_hal::sys::{esp, gpio_hold_dis, gpio_hold_en};
+use esp_idf_hal::timer::TIMER00;
+use esp_idf_hal::uart::{self, UartDriver};
+use esp_idf_hal::units::*;
+use log::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use crate::board::{BatteryProfile, BoardProfile, BOARD};
+
+// Pins are type-erased so the same drivers work with every board profile.
 pub struct Board {
+    pub profile: &'static BoardProfile,
     pub spi_bus: SpiDriver<'static>,
     // Radio Pins
-    pub lora_nss: PinDriver<'static, Gpio8, Output>,
-    pub lora_rst: PinDriver<'static, Gpio12, Output>,
-    pub lora_busy: PinDriver<'static, Gpio13, Input>,
-    pub lora_dio1: PinDriver<'static, Gpio14, Input>,
-    // Display Pins (Heltec Wireless Paper V1.1)
-    pub display_cs: PinDriver<'static, Gpio4, Output>,
-    pub display_dc: PinDriver<'static, Gpio5, Output>,
-    pub display_rst: PinDriver<'static, Gpio6, Output>,
-    pub display_busy: PinDriver<'static, Gpio7, Input>,
+    pub lora_nss: PinDriver<'static, AnyOutputPin, Output>,
+    pub lora_rst: PinDriver<'static, AnyOutputPin, Output>,
+    pub lora_busy: PinDriver<'static, AnyInputPin, Input>,
+    pub lora_dio1: PinDriver<'static, AnyInputPin, Input>,
+    // Display Pins
+    pub display_cs: PinDriver<'static, AnyOutputPin, Output>,
+    pub display_dc: PinDriver<'static, AnyOutputPin, Output>,
+    pub display_rst: PinDriver<'static, AnyOutputPin, Output>,
+    pub display_busy: PinDriver<'static, AnyInputPin, Input>,
     // Buttons
-    pub btn_select: PinDriver<'static, Gpio0, Input>,
-    // CONFLICT: User defined UP=12, DOWN=13. But these are Radio RST/BUSY.
-    // Commenting out to prevent runtime resource claiming error.
-    // pub btn_up: PinDriver<'static, Gpio12, Input>,
-    // pub btn_down: PinDriver<'static, Gpio13, Input>,
+    pub btn_select: PinDriver<'static, AnyIOPin, Input>,
+    pub btn_up: Option<PinDriver<'static, AnyIOPin, Input>>,
+    pub btn_down: Option<PinDriver<'static, AnyIOPin, Input>>,
+    pub vext: PowerRail,
+    pub led: Option<PinDriver<'static, AnyOutputPin, Output>>,
+    pub battery: Option<BatterySense>,
+    // Sensor expansion bus, on boards that bring one out.
+    pub i2c: Option<I2cDriver<'static>>,
+    pub gnss: Option<UartDriver<'static>>,
+    pub resources: Resources,
+}
+
+// The battery divider's pins with their profile.
+pub struct BatterySense {
+    pub profile: &'static BatteryProfile,
+    pub adc: AnyIOPin,
+    pub enable: Option<PinDriver<'static, AnyOutputPin, Output>>,
+}
+
+// Peripherals that `init` doesn't wire up itself, for the drivers that need
+// them. Each is a plain owned field: claiming one moves it out, so a second
+// claim anywhere in the app is a use-after-move compile error rather than a
+// failed `Peripherals::take` at runtime. Only what a driver claims is here,
+// add a field when a new one needs a peripheral.
+pub struct Resources {
+    pub timer00: TIMER00,
+    pub adc2: ADC2,
+}
+
+fn output(pin: AnyOutputPin) -> anyhow::Result<PinDriver<'static, AnyOutputPin, Output>> {
+    Ok(PinDriver::output(pin)?)
+}
+
+fn input(pin: AnyInputPin) -> anyhow::Result<PinDriver<'static, AnyInputPin, Input>> {
+    Ok(PinDriver::input(pin)?)
+}
+
+// Buttons short to ground. The internal pull-up keeps a button high when the
+// board has no external one, as with the devkit's up/down on the generic
+// profile; where there is one the two just sit in parallel.
+fn button(pin: AnyIOPin) -> anyhow::Result<PinDriver<'static, AnyIOPin, Input>> {
+    let mut driver = PinDriver::input(pin)?;
+    driver.set_pull(Pull::Up)?;
+    Ok(driver)
 }
 
 pub fn init() -> anyhow::Result<Board> {
     let peripherals = Peripherals::take()?;
-    let pins = peripherals.pins;
+    let profile = &BOARD;
+    info!("Board profile: {}", profile.name);
+    let pins = (profile.pins)(peripherals.pins);
 
-    let sclk = pins.gpio9;
-    let mosi = pins.gpio10;
-    let miso = pins.gpio11;
+    // Initialize SPI Driver (Shared Bus). DMA lets display frames and radio
+    // FIFOs go out in one transfer and completes async transfers from the ISR.
+    let config = config::DriverConfig::new().dma(Dma::Auto(4096));
+    let spi_bus = SpiDriver::new(
+        peripherals.spi2,
+        pins.spi_sclk,
+        pins.spi_mosi,
+        Some(pins.spi_miso),
+        &config,
+    )?;
 
-    // Initialize SPI Driver (Shared Bus)
-    let config = config::DriverConfig::default();
-    let spi_bus = SpiDriver::new(peripherals.spi2, sclk, mosi, Some(miso), &config)?;
+    // Standard mode, the common sensor breakouts only have weak pull-ups.
+    let i2c = match pins.i2c {
+        Some(i2c) => {
+            let config = I2cConfig::new().baudrate(100.kHz().into());
+            Some(I2cDriver::new(peripherals.i2c0, i2c.sda, i2c.scl, &config)?)
+        }
+        None => None,
+    };
 
-    // Radio
-    let lora_nss = PinDriver::output(pins.gpio8)?;
-    let lora_rst = PinDriver::output(pins.gpio12)?;
-    let lora_busy = PinDriver::input(pins.gpio13)?;
-    let lora_dio1 = PinDriver::input(pins.gpio14)?;
+    let gnss = match (pins.gnss, &profile.gnss) {
+        (Some(uart), Some(gnss)) => {
+            let config = uart::config::Config::new().baudrate(Hertz(gnss.baudrate));
+            Some(UartDriver::new(
+                peripherals.uart1,
+                uart.tx,
+                uart.rx,
+                Option::<AnyIOPin>::None,
+                Option::<AnyIOPin>::None,
+                &config,
+            )?)
+        }
+        (None, None) => None,
+        _ => anyhow::bail!("{}: GNSS pins and profile disagree", profile.name),
+    };
 
-    // Display
-    let display_cs = PinDriver::output(pins.gpio4)?;
-    let display_dc = PinDriver::output(pins.gpio5)?;
-    let display_rst = PinDriver::output(pins.gpio6)?;
-    let display_busy = PinDriver::input(pins.gpio7)?;
-
-    // Buttons
-    let btn_select = PinDriver::input(pins.gpio0)?;
+    let battery = match (pins.battery_adc, &profile.battery) {
+        (Some(adc), Some(battery)) => Some(BatterySense {
+            profile: battery,
+            adc,
+            enable: pins.battery_enable.map(output).transpose()?,
+        }),
+        (None, None) => None,
+        _ => anyhow::bail!("{}: battery pins and profile disagree", profile.name),
+    };
 
     Ok(Board {
+        profile,
         spi_bus,
-        lora_nss,
-        lora_rst,
-        lora_busy,
-        lora_dio1,
-        display_cs,
-        display_dc,
-        display_rst,
-        display_busy,
-        btn_select,
+        lora_nss: output(pins.lora_nss)?,
+        lora_rst: output(pins.lora_rst)?,
+        lora_busy: input(pins.lora_busy)?,
+        lora_dio1: input(pins.lora_dio1)?,
+        display_cs: output(pins.display_cs)?,
+        display_dc: output(pins.display_dc)?,
+        display_rst: output(pins.display_rst)?,
+        display_busy: input(pins.display_busy)?,
+        btn_select: button(pins.btn_select)?,
+        btn_up: pins.btn_up.map(button).transpose()?,
+        btn_down: pins.btn_down.map(button).transpose()?,
+        vext: PowerRail::new(pins.vext.map(output).transpose()?, profile.vext_active_low)?,
+        led: pins.led.map(output).transpose()?,
+        battery,
+        i2c,
+        gnss,
+        resources: Resources {
+            timer00: peripherals.timer00,
+            adc2: peripherals.adc2,
+        },
     })
 }
+
+// Low four bytes of the factory MAC, stable for the lifetime of the board.
+pub fn node_id() -> u32 {
+    let mut mac = [0u8; 6];
+    unsafe { esp_idf_hal::sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) };
+    u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]])
+}
+
+// Regulators need a moment before the load sees a stable supply.
+const RAIL_SETTLE: Duration = Duration::from_millis(10);
+
+struct RailState {
+    // `None` on boards where the supply is not switched, it is then always on.
+    pin: Option<PinDriver<'static, AnyOutputPin, Output>>,
+    active_low: bool,
+    users: usize,
+}
+
+impl RailState {
+    fn switch(&mut self, on: bool) -> anyhow::Result<()> {
+        if let Some(pin) = &mut self.pin {
+            pin.set_level((on != self.active_low).into())?;
+        }
+        Ok(())
+    }
+}
+
+// A switched supply shared by several drivers. It is on while at least one
+// `RailGuard` is alive, so one user powering down never cuts another.
+#[derive(Clone)]
+pub struct PowerRail {
+    state: Arc<Mutex<RailState>>,
+}
+
+impl PowerRail {
+    // Starts off. A pin still latched from deep sleep is released first.
+    pub fn new(
+        pin: Option<PinDriver<'static, AnyOutputPin, Output>>,
+        active_low: bool,
+    ) -> anyhow::Result<Self> {
+        if let Some(pin) = &pin {
+            esp!(unsafe { gpio_hold_dis(pin.pin()) })?;
+        }
+
+        let mut state = RailState {
+            pin,
+            active_low,
+            users: 0,
+        };
+        state.switch(false)?;
+
+        Ok(Self {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    // Powers the rail up if this is the first user. Returns once it is stable.
+    pub fn acquire(&self) -> anyhow::Result<RailGuard> {
+        let mut state = self.state.lock().unwrap();
+        if state.users == 0 {
+            state.switch(true)?;
+            if state.pin.is_some() {
+                std::thread::sleep(RAIL_SETTLE);
+            }
+        }
+        state.users += 1;
+
+        Ok(RailGuard { rail: self.clone() })
+    }
+
+    // Latches the current level so the rail stays as it is through deep sleep.
+    pub fn hold_for_deep_sleep(&self) -> anyhow::Result<()> {
+        let state = self.state.lock().unwrap();
+        if let Some(pin) = &state.pin {
+            esp!(unsafe { gpio_hold_en(pin.pin()) })?;
+        }
+        Ok(())
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.users -= 1;
+        if state.users == 0 {
+            if let Err(e) = state.switch(false) {
+                warn!("Failed to switch power rail off: {:?}", e);
+            }
+        }
+    }
+}
+
+// Keeps the rail powered until dropped.
+pub struct RailGuard {
+    rail: PowerRail,
+}
+
+impl Drop for RailGuard {
+    fn drop(&mut self) {
+        self.rail.release();
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use embedded_graphics::{
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle, Triangle},
+    text::{Baseline, Text},
+};
+use epd_waveshare::color::Color;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::font::FontStyle;
+use crate::ui;
+
+// Whole inbox in one blob, as stored before messages got a slot each.
+const LEGACY_KEY: &str = "inbox";
+// Oldest messages are dropped past this, each one has its own NVS slot.
+const CAPACITY: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Message {
+    pub sender: String,
+    // Seconds since the Unix epoch, as far as the device clock knows.
+    pub timestamp: u64,
+    pub text: String,
+    pub read: bool,
+}
+
+impl Message {
+    // HH:MM (UTC) for list rows and headers.
+    pub fn time_of_day(&self) -> String {
+        let minutes = self.timestamp / 60 % (24 * 60);
+        format!("{:02}:{:02}", minutes / 60, minutes % 60)
+    }
+}
+
+// One NVS entry per message, so a new or read message rewrites a few hundred
+// bytes rather than the whole inbox. `seq` counts up with every message and
+// picks the slot, `seq % CAPACITY`, overwriting the oldest.
+#[derive(Serialize, Deserialize)]
+struct Slot {
+    seq: u32,
+    message: Message,
+}
+
+fn slot_key(seq: u32) -> String {
+    format!("inbox{}", seq as usize % CAPACITY)
+}
+
+// Received messages, newest first, saved to NVS on every change.
+pub struct Inbox {
+    messages: VecDeque<Message>,
+    // Sequence number of each message, in the same order.
+    seqs: VecDeque<u32>,
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl Inbox {
+    pub fn load(partition: EspDefaultNvsPartition) -> anyhow::Result<Self> {
+        let nvs = EspNvs::new(partition, crate::settings::NAMESPACE, true)?;
+
+        let mut inbox = Self {
+            messages: VecDeque::new(),
+            seqs: VecDeque::new(),
+            nvs,
+        };
+        if let Err(e) = inbox.migrate() {
+            warn!("Stored inbox unreadable, dropping it: {:?}", e);
+            inbox.nvs.remove(LEGACY_KEY)?;
+        }
+
+        let mut slots: Vec<Slot> = (0..CAPACITY as u32)
+            .filter_map(|index| match inbox.read_blob::<Slot>(&slot_key(index)) {
+                Ok(slot) => slot,
+                Err(e) => {
+                    warn!("Inbox slot {} unreadable, skipped: {:?}", index, e);
+                    None
+                }
+            })
+            .collect();
+        slots.sort_by(|a, b| b.seq.cmp(&a.seq));
+        for slot in slots {
+            inbox.seqs.push_back(slot.seq);
+            inbox.messages.push_back(slot.message);
+        }
+
+        Ok(inbox)
+    }
+
+    fn read_blob<T: serde::de::DeserializeOwned>(&self, key: &str) -> anyhow::Result<Option<T>> {
+        let Some(len) = self.nvs.blob_len(key)? else {
+            return Ok(None);
+        };
+
+        let mut buf = vec![0; len];
+        match self.nvs.get_blob(key, &mut buf)? {
+            Some(json) => Ok(Some(serde_json::from_slice(json)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Moves a single-blob inbox into slots, oldest message first.
+    fn migrate(&mut self) -> anyhow::Result<()> {
+        let Some(messages) = self.read_blob::<VecDeque<Message>>(LEGACY_KEY)? else {
+            return Ok(());
+        };
+
+        for (seq, message) in messages.into_iter().rev().enumerate() {
+            self.write(&Slot {
+                seq: seq as u32,
+                message,
+            })?;
+        }
+        self.nvs.remove(LEGACY_KEY)?;
+        info!("Inbox moved to per-message storage");
+        Ok(())
+    }
+
+    fn write(&mut self, slot: &Slot) -> anyhow::Result<()> {
+        let json = serde_json::to_vec(slot)?;
+        self.nvs.set_blob(&slot_key(slot.seq), &json)?;
+        Ok(())
+    }
+
+    pub fn push(&mut self, sender: &str, text: &str) -> anyhow::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        let slot = Slot {
+            seq: self.seqs.front().map_or(0, |seq| seq.wrapping_add(1)),
+            message: Message {
+                sender: sender.to_string(),
+                timestamp,
+                text: text.to_string(),
+                read: false,
+            },
+        };
+        self.write(&slot)?;
+
+        self.seqs.push_front(slot.seq);
+        self.messages.push_front(slot.message);
+        self.seqs.truncate(CAPACITY);
+        self.messages.truncate(CAPACITY);
+        Ok(())
+    }
+
+    pub fn messages(&self) -> &VecDeque<Message> {
+        &self.messages
+    }
+
+    pub fn unread(&self) -> usize {
+        self.messages.iter().filter(|m| !m.read).count()
+    }
+
+    pub fn mark_read(&mut self, index: usize) -> anyhow::Result<()> {
+        match self.messages.get_mut(index) {
+            Some(message) if !message.read => {
+                message.read = true;
+                let slot = Slot {
+                    seq: self.seqs[index],
+                    message: message.clone(),
+                };
+                self.write(&slot)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Inbox screen state, driven by the select button: a short press moves down
+// the list or pages through an open message, a long press opens the selected
+// message or goes back to the list.
+pub enum InboxView {
+    List { selected: usize },
+    Message { index: usize, first_line: usize },
+}
+
+impl Default for InboxView {
+    fn default() -> Self {
+        InboxView::List { selected: 0 }
+    }
+}
+
+impl InboxView {
+    // `screen` is the drawable size, needed to know how long a page is.
+    pub fn next(&mut self, inbox: &Inbox, screen: Size) {
+        match self {
+            InboxView::List { selected } => {
+                let count = inbox.messages().len().max(1);
+                *selected = (*selected + 1) % count;
+            }
+            InboxView::Message { index, first_line } => {
+                let Some(message) = inbox.messages().get(*index) else {
+                    return;
+                };
+                let body = ui::body_area(Rectangle::new(Point::zero(), screen));
+                let lines = ui::body_font().wrap(&message.text, body.size.width).len();
+                let page = ui::lines_per_page(body);
+
+                // Wrap back to the top after the last page.
+                *first_line = if *first_line + page < lines {
+                    *first_line + page
+                } else {
+                    0
+                };
+            }
+        }
+    }
+
+    pub fn select(&mut self, inbox: &mut Inbox) -> anyhow::Result<()> {
+        match *self {
+            InboxView::List { selected } if selected < inbox.messages().len() => {
+                inbox.mark_read(selected)?;
+                *self = InboxView::Message {
+                    index: selected,
+                    first_line: 0,
+                };
+            }
+            InboxView::List { .. } => {}
+            InboxView::Message { index, .. } => *self = InboxView::List { selected: index },
+        }
+        Ok(())
+    }
+
+    pub fn draw<D>(&self, target: &mut D, inbox: &Inbox) -> anyhow::Result<()>
+    where
+        D: DrawTarget<Color = Color>,
+    {
+        match *self {
+            InboxView::List { selected } => draw_list(target, inbox, selected),
+            InboxView::Message { index, first_line } => match inbox.messages().get(index) {
+                Some(message) => draw_message(target, message, first_line),
+                None => draw_list(target, inbox, 0),
+            },
+        }
+        .map_err(|_| anyhow::anyhow!("Inbox draw failed"))
+    }
+}
+
+fn draw_list<D>(target: &mut D, inbox: &Inbox, selected: usize) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Color>,
+{
+    ui::draw_header(target, "Inbox", &format!("{} unread", inbox.unread()))?;
+
+    // Unread messages are marked with a bullet.
+    let rows: Vec<String> = inbox
+        .messages()
+        .iter()
+        .map(|m| {
+            let marker = if m.read { " " } else { "•" };
+            format!("{} {} {}: {}", marker, m.time_of_day(), m.sender, m.text)
+        })
+        .collect();
+
+    ui::draw_list(target, &rows, selected, "No messages")
+}
+
+fn draw_message<D>(target: &mut D, message: &Message, first_line: usize) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Color>,
+{
+    ui::draw_header(target, &message.sender, &message.time_of_day())?;
+
+    let body = ui::body_area(target.bounding_box());
+    let font = ui::body_font();
+    let lines = font.wrap(&message.text, body.size.width);
+    let page = ui::lines_per_page(body);
+    let style = FontStyle::new(font, Color::Black);
+
+    for (row, line) in lines.iter().skip(first_line).take(page).enumerate() {
+        let origin = body.top_left + Point::new(0, row as i32 * font.line_height() as i32);
+        Text::with_baseline(line, origin, style, Baseline::Top).draw(target)?;
+    }
+
+    // More below: a small arrow in the bottom right corner.
+    if first_line + page < lines.len() {
+        let corner = body.top_left + body.size - Size::new(1, 1);
+        Triangle::new(
+            corner - Point::new(8, 5),
+            corner - Point::new(0, 5),
+            corner - Point::new(4, 1),
+        )
+        .into_styled(PrimitiveStyle::with_fill(Color::Black))
+        .draw(target)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use std::sync::{Arc, Mutex};
+
+use esp_idf_hal::gpio::*;
+use esp_idf_svc::timer::{EspTaskTimerService, EspTimer};
+use log::*;
+use tugger_core::led::{Pattern, Sequencer, TICK};
+
+// The board's status LED, blinking on an esp_timer so the state shows
+// without waiting for an e-paper refresh. Boards without one get a driver
+// that does nothing.
+pub struct StatusLed {
+    sequencer: Arc<Mutex<Sequencer>>,
+    _timer: Option<EspTimer<'static>>,
+}
+
+impl StatusLed {
+    pub fn new(
+        pin: Option<PinDriver<'static, AnyOutputPin, Output>>,
+        timers: &EspTaskTimerService,
+    ) -> anyhow::Result<Self> {
+        let sequencer = Arc::new(Mutex::new(Sequencer::new(Pattern::Booting)));
+
+        let timer = match pin {
+            Some(mut pin) => {
+                let state = sequencer.clone();
+                let mut lit = None;
+                let timer = timers.timer(move || {
+                    let on = state.lock().unwrap().tick();
+                    if lit != Some(on) {
+                        if let Err(e) = pin.set_level(on.into()) {
+                            warn!("Status LED write failed: {:?}", e);
+                        }
+                        lit = Some(on);
+                    }
+                })?;
+                timer.every(TICK)?;
+                Some(timer)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            sequencer,
+            _timer: timer,
+        })
+    }
+
+    // Repeating state, e.g. `LowBattery` or `Off`.
+    pub fn set(&self, pattern: Pattern) {
+        self.sequencer.lock().unwrap().set_background(pattern);
+    }
+
+    // One-off event such as `Tx`, the background resumes after it.
+    pub fn play(&self, pattern: Pattern) {
+        self.sequencer.lock().unwrap().play(pattern);
+    }
+}
@@ -1,73 +1,40 @@
-```
 // embedded-hal-bus 0.1 location:
 // use esp_idf_hal::gpio::*;
+use embassy_futures::join::join;
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
 use esp_idf_hal::task::block_on;
 use esp_idf_svc::hal as esp_idf_hal;
 use log::*;
-use std::sync::Mutex;
+use tugger_core::battery::{BatteryLevel, BatteryMonitor};
+use tugger_core::led::Pattern;
+use tugger_core::spitrace;
 
+mod app;
+mod assets;
+mod battery;
+mod board;
+mod button;
+mod chart;
+mod composer;
 mod display;
+mod event;
+mod font;
+mod framebuffer;
+mod gnss;
 mod hardware;
+mod inbox;
+mod led;
+mod panel;
+mod power;
+mod qr;
 mod radio;
-
-// Custom declaration of SpiBus trait to ensure visibility/scope if needed, 
-// strictly speaking we should import it from embedded_hal.
-use embedded_hal::spi::SpiBus;
-
-// SimpleMutexSpiDevice: A custom SpiDevice implementation that shares a bus via Mutex, 
-// but does NOT handle Chip Select (CS). This allows the consumer (Driver) to manage CS.
-pub struct SimpleMutexSpiDevice<'a, T>(pub &'a Mutex<T>);
-
-impl<'a, T: SpiBus> embedded_hal::spi::ErrorType for SimpleMutexSpiDevice<'a, T> {
-    type Error = T::Error;
-}
-
-impl<'a, T: SpiBus> embedded_hal::spi::SpiDevice for SimpleMutexSpiDevice<'a, T> {
-    fn transaction(
-        &mut self,
-        operations: &mut [embedded_hal::spi::Operation<'_, u8>],
-    ) -> Result<(), Self::Error> {
-        let mut bus = self.0.lock().unwrap();
-        for op in operations {
-            match op {
-                embedded_hal::spi::Operation::Read(buf) => bus.read(buf)?,
-                embedded_hal::spi::Operation::Write(buf) => bus.write(buf)?,
-                embedded_hal::spi::Operation::Transfer(read, write) => bus.transfer(read, write)?,
-                embedded_hal::spi::Operation::TransferInPlace(buf) => bus.transfer_in_place(buf)?,
-                embedded_hal::spi::Operation::DelayNs(ns) => {
-                    // Primitive delay if supported, or ignore? 
-                    // Verify if esp_idf_hal::spi::SpiBus supports delay/flush?
-                    // SpiBus trait 1.0 includes flush. Delay is in Operation.
-                    // If Bus doesn't support delay, we might need a delayer?
-                    // For now, ignore delay or implementation specific.
-                    // But SpiBus doesn't have `delay_ns`.
-                    // We must just perform a flush.
-                    let _ = *ns; 
-                    bus.flush()?;
-                }
-            }
-        }
-        bus.flush()?;
-        Ok(())
-    }
-}
-
-// Refactored BlockingAsyncSpi to wrap our SimpleMutexSpiDevice
-pub struct BlockingAsyncSpi<T>(T);
-
-impl<T: embedded_hal::spi::ErrorType> embedded_hal::spi::ErrorType for BlockingAsyncSpi<T> {
-    type Error = T::Error;
-}
-
-impl<T: embedded_hal::spi::SpiDevice> embedded_hal_async::spi::SpiDevice for BlockingAsyncSpi<T> {
-    async fn transaction(
-        &mut self,
-        operations: &mut [embedded_hal::spi::Operation<'_, u8>],
-    ) -> Result<(), T::Error> {
-        // Just call the blocking transaction
-        self.0.transaction(operations)
-    }
-}
+mod selftest;
+mod sensor;
+mod settings;
+mod spi;
+mod ui;
 
 fn main() -> anyhow::Result<()> {
     // Check-cfg are handled in build.rs
@@ -76,95 +43,389 @@ fn main() -> anyhow::Result<()> {
 
     info!("Starting Tugger Device...");
 
-    let board = hardware::init()?;
-
-    // Create Shared SPI Bus
-    // Note: hardware::init() returns a struct with `spi_bus`. 
-    // Ensure `spi_bus` implements `SpiBus`.
-    let spi_bus = Mutex::new(board.spi_bus);
-
-    // Blocking Device for Display
-    // We use SimpleMutexSpiDevice so `display` handles CS itself.
-    let mut display_spi = SimpleMutexSpiDevice(&spi_bus);
-
-    // Downgrade busy pin for display to generic Input
-    // board.display_busy is concrete PinDriver. 
-    // Epd2in9 might expect concrete. If we previously changed it to `AnyInputPin`, we'd need conversion.
-    // Checking display.rs: `busy: PinDriver<'static, Gpio7, Input>` -> concrete!
-    // So we just pass it directly.
-    
-    // For Epd2in9::new, correct signature from recent findings?
-    // Epd2in9::new(spi, cs, dc, rst, busy, delay) (?) - 6 args? 
-    // Or (spi, cs, dc, rst, delay, speed)?
-    // Round 2 fix used 6 args with busy. 
-    // User error report said "E0061 ... missing spi_speed Option<u32>".
-    // So current hypothesis: (spi, cs, dc, rst, busy, delay, spi_speed). 7 args?
-    // Or (spi, cs, dc, rst, delay, spi_speed). 6 args (NO BUSY).
-    // Let's assume NO BUSY in constructor, setting busy separately if needed.
-    // I will try 6 args: (spi, cs, dc, rst, delay, None).
-    
+    let mut board = hardware::init()?;
+    let timers = esp_idf_svc::timer::EspTaskTimerService::new()?;
+    let led = led::StatusLed::new(board.led, &timers)?;
+
+    // Latched through deep sleep, see `TunggerDisplay::prepare_for_deep_sleep`.
+    let display_hold_pins = vec![
+        board.display_cs.pin(),
+        board.display_dc.pin(),
+        board.display_rst.pin(),
+    ];
+
+    // Shared SPI bus, every device owns its CS and bus settings.
+    let spi_bus = spi::SharedBus::new(board.spi_bus);
+    let spi_trace = spitrace::TraceBuffer::new(spitrace::CAPACITY);
+    let mut display_spi = spitrace::Traced::new(
+        spi_bus.device(board.display_cs, &display::SPI_CONFIG)?,
+        "display",
+        spi_trace.clone(),
+    );
+
+    let nvs = esp_idf_svc::nvs::EspDefaultNvsPartition::take()?;
+    let mut settings_store = settings::SettingsStore::new(nvs.clone())?;
+    let settings = settings_store.load();
+    let provisioned = settings_store.stored()?;
+    let inbox = inbox::Inbox::load(nvs.clone())?;
+
     info!("Initializing Display...");
-    // Commenting out Display init temporarily if it causes issues, but let's try to fix it.
-    // We pass `display_spi`.
-    // We need logic to handle `busy` if not passed to constructor.
-    // But `TunggerDisplay::new` signature in `display.rs` expects `busy`.
-    // I should update `display.rs` too. For now let's update `main.rs` call.
-    // Display Init
-    // Epd2in9::new now takes (spi, cs, dc, rst, delay, speed)
+    // A panel chosen in the settings overrides the board's factory glass.
+    let panel = match settings.panel {
+        panel::PanelKind::Auto => board.profile.panel,
+        kind => kind,
+    };
     let mut display = display::TunggerDisplay::new(
         &mut display_spi,
-        board.display_cs,
+        panel,
+        display_hold_pins,
         board.display_dc,
         board.display_rst,
+        board.display_busy,
+        board.vext.clone(),
     )?;
+    display.set_rotation(settings.rotation);
+    let mut app = app::App::new(settings, inbox, hardware::node_id());
 
-    display.update(&mut display_spi, "Booting...")?;
+    let mut power = power::PowerManager::new(
+        power::PowerConfig::default(),
+        board.btn_select.pin(),
+        board.lora_dio1.pin(),
+    );
+    let boot_cause = power.boot_cause();
+    info!("Boot cause: {:?}", boot_cause);
+    // Pressing select right after power-on and holding it for a second runs
+    // the self-test, sampled before the slow logo refresh. A button wake
+    // from deep sleep has it held as well, so only a cold boot counts.
+    let self_test =
+        boot_cause == power::WakeCause::PowerOn && selftest::requested(&mut board.btn_select);
+    if let Some(state) = power::retained(boot_cause) {
+        app.restore(state);
+    }
+    // Until settings have been saved the unit is fresh off the line, a cold
+    // boot opens on the QR code the installer scans.
+    if boot_cause == power::WakeCause::PowerOn && !provisioned {
+        app.show_provisioning();
+    }
+
+    display.clear();
+    display.draw_icon(
+        &assets::LOGO,
+        embedded_graphics::prelude::Point::new(8, 8),
+        assets::IconStyle::default(),
+    )?;
+    display.refresh(&mut display_spi)?;
     info!("Display Initialized.");
 
-    // Async Device for Radio
-    // Wrap the blocking SimpleMutexSpiDevice in our adapter
-    let radio_spi = BlockingAsyncSpi(SimpleMutexSpiDevice(&spi_bus));
+    let mut report = selftest::Report::default();
+    if self_test {
+        info!("Running self-test...");
+        let busy = display.check_busy(&mut display_spi);
+        report.record("display", busy.map(|t| format!("reset in {} ms", t.as_millis())));
+        report.record("storage", selftest::storage(nvs));
+        report.record("psram", selftest::psram());
+    }
+
+    let (events_tx, events) = event::queue();
+    let mut buttons = vec![(button::ButtonId::Select, board.btn_select)];
+    buttons.extend(board.btn_up.map(|pin| (button::ButtonId::Up, pin)));
+    buttons.extend(board.btn_down.map(|pin| (button::ButtonId::Down, pin)));
+    button::spawn(buttons, events_tx.clone())?;
+
+    match board.battery {
+        Some(sense) => {
+            // The supported boards sense the battery on GPIO11-20, ADC2.
+            let mut adc = battery::BatteryAdc::new(sense, board.resources.adc2)?;
+            if self_test {
+                report.record("battery", selftest::battery(&mut adc));
+            }
+            battery::spawn(
+                adc,
+                BatteryMonitor::new(app.settings.battery_calibration),
+                events_tx.clone(),
+            )?
+        }
+        None => info!("No battery sense on this board"),
+    }
+
+    match board.i2c {
+        Some(i2c) => sensor::spawn(i2c, board.vext.clone(), events_tx.clone())?,
+        None => info!("No sensor bus on this board"),
+    }
+
+    match board.gnss {
+        Some(uart) => gnss::spawn(uart, events_tx.clone())?,
+        None => info!("No GNSS on this board"),
+    }
 
-    // Downgrade pins for Radio
-    // Use `into_output()` and `into_input()` (not any).
-    // `PinDriver` should have these methods to convert to dynamic pin driver.
-    // If board.lora_nss is PinDriver<Gpio8, Output>, .into_output()?
-    // Let's try explicit map if needed, but error says use `into_output`.
-    let lora_nss = board.lora_nss.into_output().map_err(|e| anyhow::anyhow!("Pin error {:?}", e))?;
-    let lora_rst = board.lora_rst.into_output().map_err(|e| anyhow::anyhow!("Pin error {:?}", e))?;
-    let lora_busy = board.lora_busy.into_input().map_err(|e| anyhow::anyhow!("Pin error {:?}", e))?;
-    let lora_dio1 = board.lora_dio1.into_input().map_err(|e| anyhow::anyhow!("Pin error {:?}", e))?;
+    // The radio driver is async, its transfers yield instead of blocking the
+    // executor.
+    let mut radio_spi = spitrace::Traced::new(
+        spi_bus.async_device(board.lora_nss, &radio::SPI_CONFIG, timers.timer_async()?)?,
+        "radio",
+        spi_trace.clone(),
+    );
+    let (mut lora_rst, mut lora_busy) = (board.lora_rst, board.lora_busy);
+    let radio_timer = esp_idf_hal::timer::TimerDriver::new(
+        board.resources.timer00,
+        &esp_idf_hal::timer::config::Config::new(),
+    )?;
 
     block_on(async {
+        // The packet behind a deep sleep wake is still in the radio, read it
+        // before the driver's reset drops it.
+        let woke_with = if boot_cause == power::WakeCause::Radio {
+            let delay = spi::AsyncSpiDelay::new(timers.timer_async()?);
+            match radio::take_pending(&mut radio_spi, &mut lora_busy, delay).await {
+                Ok(packet) => packet,
+                Err(e) => {
+                    warn!("Reading the wake packet failed: {:?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        if self_test {
+            let radio = selftest::radio(&mut radio_spi, &mut lora_rst, &mut lora_busy);
+            report.record("radio", radio.await);
+        }
+
         info!("Initializing Radio (Async)...");
 
-        // Initialize TimerDriver for LoRa delay
-        // esp-idf-hal 0.45: `TimerDriver::new`
-        let peripherals = esp_idf_hal::peripherals::Peripherals::take()?;
-        let timer_driver = esp_idf_hal::timer::TimerDriver::new(peripherals.timer00, &esp_idf_hal::timer::config::Config::new())?;
-
-        let mut radio = radio::TunggerRadio::new(
-            radio_spi,
-            lora_nss,
-            lora_rst,
-            lora_busy,
-            lora_dio1,
-            timer_driver,
-        )
-        .await?;
-
-        radio.configure(&radio::RadioConfig::default()).await?;
+        let radio_config = radio::RadioConfig::default();
+        let radio = async {
+            let mut radio = radio::TunggerRadio::new(
+                radio_spi,
+                lora_rst,
+                lora_busy,
+                board.lora_dio1,
+                spi::AsyncSpiDelay::new(timers.timer_async()?),
+                radio_timer,
+            )
+            .await?;
+            radio.configure(&radio_config).await?;
+            radio.listen(&radio_config).await?;
+            anyhow::Ok(radio)
+        }
+        .await;
+
+        // A failed init still gets the self-test report onto the panel
+        // before it ends the boot.
+        let mut radio = match radio {
+            Ok(radio) => {
+                if self_test {
+                    report.record("radio init", Ok("listening".into()));
+                    report.log();
+                }
+                radio
+            }
+            Err(e) if self_test => {
+                report.record("radio init", Err(e));
+                report.log();
+                display.show(&mut display_spi, |d| report.draw(d))?;
+                anyhow::bail!("Radio init failed");
+            }
+            Err(e) => return Err(e),
+        };
         info!("Radio Initialized.");
+        led.set(Pattern::Off);
+        led.play(match boot_cause {
+            power::WakeCause::Radio => Pattern::Rx,
+            _ => Pattern::Joined,
+        });
 
-        loop {
-             // Logic loop
-             std::thread::sleep(std::time::Duration::from_secs(5));
-             
-             display.update(&mut display_spi, "Tick")?;
-             info!("Tick");
+        if let Some(packet) = woke_with.as_deref().and_then(radio::TextPacket::decode) {
+            app.on_message(packet)?;
+        }
+
+        // The report stays up until the first button press.
+        if self_test {
+            display.show(&mut display_spi, |d| report.draw(d))?;
+        } else {
+            display.show(&mut display_spi, |d| app.draw(d))?;
         }
-    })?;
 
-    Ok(())
+        // Events from the other tasks, packets off the air and the power
+        // timer, whichever comes first.
+        let mut power_timer = timers.timer_async()?;
+        let mut display_delay = spi::AsyncSpiDelay::new(timers.timer_async()?);
+        let mut rx_buf = [0u8; radio::MAX_PAYLOAD];
+        let mut radio_woke = false;
+        loop {
+            // After a light sleep radio wake DIO1 is known to be up, go
+            // straight to reading the packet.
+            let next = if std::mem::take(&mut radio_woke) {
+                Either3::Second(Ok(()))
+            } else {
+                select3(
+                    events.recv(),
+                    radio.wait_for_irq(),
+                    power_timer.after(power.until_due()),
+                )
+                .await
+            };
+            let event = match next {
+                Either3::First(event) => event?,
+                Either3::Second(irq) => {
+                    irq?;
+                    let len = match radio.receive(&mut rx_buf).await {
+                        Ok(Some((len, rssi))) => {
+                            events_tx.send(event::Event::Rssi(rssi))?;
+                            len
+                        }
+                        Ok(None) => continue,
+                        Err(e) => {
+                            warn!("Receive failed: {:?}", e);
+                            continue;
+                        }
+                    };
+                    // Position reports and anything else not for the inbox.
+                    let Some(packet) = radio::TextPacket::decode(&rx_buf[..len]) else {
+                        debug!("Ignored {} byte packet", len);
+                        continue;
+                    };
+                    event::Event::Message(packet)
+                }
+                Either3::Third(timer) => {
+                    timer?;
+                    match power.due() {
+                        Some(power::Sleep::Deep) => {
+                            display.prepare_for_deep_sleep(&mut display_spi)?;
+                            match power.deep_sleep(app.retained())? {}
+                        }
+                        Some(power::Sleep::Light) => match power.light_sleep()? {
+                            power::WakeCause::Radio => radio_woke = true,
+                            // The button task posts its own press, a timer
+                            // wake is for the other tasks' periodic work.
+                            _ => {}
+                        },
+                        None => {}
+                    }
+                    continue;
+                }
+            };
+
+            let action = match event {
+                event::Event::Button(button) => {
+                    power.activity();
+                    app.on_button(button, display.size())?
+                }
+                event::Event::Battery(status) if status.level == BatteryLevel::Critical => {
+                    warn!("Battery critical ({} mV), shutting down", status.millivolts);
+                    display.show(&mut display_spi, |d| {
+                        ui::draw_header(d, "Battery empty", "")
+                            .and_then(|_| {
+                                let text = "Charge, then press the button to restart.".to_string();
+                                ui::draw_lines(d, &[text])
+                            })
+                            .map_err(|_| anyhow::anyhow!("Shutdown screen draw failed"))
+                    })?;
+                    display.prepare_for_deep_sleep(&mut display_spi)?;
+                    match power.shutdown()? {}
+                }
+                event::Event::Sensor(sample) => {
+                    // Nothing on screen uses these yet, telemetry picks them up.
+                    info!("{} at {:#04x}: {}", sample.sensor, sample.address, sample.reading);
+                    app::Action::None
+                }
+                event::Event::Gnss(fix) if fix.valid => {
+                    info!(
+                        "Position {:.6}, {:.6} ({} sats), {:.1} km/h",
+                        fix.latitude, fix.longitude, fix.satellites, fix.speed_kmh
+                    );
+                    let packet = radio::PositionPacket {
+                        from: hardware::node_id(),
+                        latitude: fix.latitude,
+                        longitude: fix.longitude,
+                        altitude_m: fix.altitude_m,
+                        satellites: fix.satellites,
+                    };
+                    if let Err(e) = radio.send_position(&packet).await {
+                        warn!("Position report failed: {:?}", e);
+                        spi_trace.dump();
+                    }
+                    radio.listen(&radio_config).await?;
+                    app::Action::None
+                }
+                event::Event::Gnss(_) => {
+                    info!("No GNSS fix");
+                    app::Action::None
+                }
+                event::Event::Message(packet) => {
+                    power.activity();
+                    led.play(Pattern::Rx);
+                    app.on_message(packet)?
+                }
+                event::Event::Rssi(rssi) => app.on_rssi(rssi),
+                event::Event::Battery(status) => {
+                    led.set(match status.level {
+                        BatteryLevel::Low => Pattern::LowBattery,
+                        _ => Pattern::Off,
+                    });
+                    app.on_battery(status)
+                }
+            };
+
+            match action {
+                app::Action::None => continue,
+                app::Action::Redraw => {}
+                app::Action::Send(packet) => {
+                    // Put "Sending…" up while the packet is on air.
+                    display.show(&mut display_spi, |d| app.draw(d))?;
+                    led.play(Pattern::Tx);
+                    let status = match radio.send_text(&packet).await {
+                        Ok(()) => composer::DeliveryStatus::Sent,
+                        Err(e) => {
+                            led.play(Pattern::Error);
+                            spi_trace.dump();
+                            composer::DeliveryStatus::Failed(e.to_string())
+                        }
+                    };
+                    app.delivery(status);
+                    radio.listen(&radio_config).await?;
+                }
+                app::Action::SettingsChanged => {
+                    if let Err(e) = settings_store.save(&app.settings) {
+                        warn!("Saving settings failed: {:?}", e);
+                    }
+                    display.set_rotation(app.settings.rotation);
+                }
+            }
+
+            // The panel's busy wait yields, so the radio keeps receiving
+            // while it updates. What came in goes through the queue like any
+            // other event. The display's transfers block on the bus lock, so
+            // they only run while the radio is not in the middle of one:
+            // before the join, and after `receive_until` has returned.
+            let refreshed = Signal::<CriticalSectionRawMutex, ()>::new();
+            let mut received = Vec::new();
+            let mut receiving = Ok(());
+            let shown = match display.begin_show(&mut display_spi, |d| app.draw(d)) {
+                Ok(()) => {
+                    let (shown, received_ok) = join(
+                        async {
+                            let shown = display.wait_shown(&mut display_delay).await;
+                            refreshed.signal(());
+                            shown
+                        },
+                        radio.receive_until(refreshed.wait(), &mut received),
+                    )
+                    .await;
+                    receiving = received_ok;
+                    shown
+                }
+                Err(e) => Err(e),
+            };
+            display.end_show(&mut display_spi, shown)?;
+            receiving?;
+            for (data, rssi) in &received {
+                events_tx.send(event::Event::Rssi(*rssi))?;
+                if let Some(packet) = radio::TextPacket::decode(data) {
+                    events_tx.send(event::Event::Message(packet))?;
+                }
+            }
+        }
+    })
 }
-```
@@ -0,0 +1,135 @@
+use embedded_hal::digital::{InputPin, OutputPin};
+use epd_waveshare::{epd2in13_v2, epd2in9, epd2in9_v2, epd2in9bc, prelude::*};
+use esp_idf_hal::delay::{Ets, FreeRtos};
+use esp_idf_hal::gpio::Level;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::display::BusyPin;
+
+// E-paper panels the firmware can drive, all black/white through
+// epd-waveshare. Heltec has shipped different glass across board revisions,
+// other Waveshare/GoodDisplay modules wired to the same pins work too.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PanelKind {
+    // Pick by probing the controller at boot, see `detect`.
+    #[default]
+    Auto,
+    // 2.9" 296x128, SSD1680.
+    Epd2in9V2,
+    // 2.9" 296x128, IL3820 (the original Waveshare V1).
+    Epd2in9,
+    // 2.13" 250x122, SSD1675B.
+    Epd2in13V2,
+    // 2.9" 296x128, UC8151 (three colour glass, driven black/white only).
+    Epd2in9bc,
+}
+
+impl PanelKind {
+    // Level of the busy line while the controller is working.
+    pub fn busy_level(self) -> Level {
+        match self {
+            PanelKind::Epd2in9bc => Level::Low,
+            _ => Level::High,
+        }
+    }
+}
+
+// Hardware reset, then read the idle busy level. SSD16xx/IL38xx controllers
+// signal busy high and idle low, UC81xx controllers the other way round. The
+// panel size can't be read back, so each family maps to its most common part.
+pub fn detect(busy: &mut impl InputPin, rst: &mut impl OutputPin) -> anyhow::Result<PanelKind> {
+    let reset_failed = |_| anyhow::anyhow!("EPD reset failed");
+    rst.set_low().map_err(reset_failed)?;
+    Ets::delay_ms(10);
+    rst.set_high().map_err(reset_failed)?;
+    // Longer than either family needs to come out of reset.
+    FreeRtos::delay_ms(100);
+
+    let high = busy
+        .is_high()
+        .map_err(|_| anyhow::anyhow!("EPD busy read failed"))?;
+    let kind = if high {
+        PanelKind::Epd2in9bc
+    } else {
+        PanelKind::Epd2in9V2
+    };
+    info!("Detected EPD panel {:?}", kind);
+
+    Ok(kind)
+}
+
+// One of the epd-waveshare drivers, picked at runtime. The drivers share the
+// `WaveshareDisplay` trait but it is not object safe, hence the enum.
+pub enum AnyEpd<SPI, BUSY, DC, RST> {
+    Epd2in9V2(epd2in9_v2::Epd2in9<SPI, BusyPin<BUSY>, DC, RST, Ets>),
+    Epd2in9(epd2in9::Epd2in9<SPI, BusyPin<BUSY>, DC, RST, Ets>),
+    Epd2in13V2(epd2in13_v2::Epd2in13<SPI, BusyPin<BUSY>, DC, RST, Ets>),
+    Epd2in9bc(epd2in9bc::Epd2in9bc<SPI, BusyPin<BUSY>, DC, RST, Ets>),
+}
+
+macro_rules! dispatch {
+    ($self:expr, $epd:ident => $body:expr) => {
+        match $self {
+            AnyEpd::Epd2in9V2($epd) => $body,
+            AnyEpd::Epd2in9($epd) => $body,
+            AnyEpd::Epd2in13V2($epd) => $body,
+            AnyEpd::Epd2in9bc($epd) => $body,
+        }
+    };
+}
+
+impl<SPI, BUSY, DC, RST> AnyEpd<SPI, BUSY, DC, RST>
+where
+    SPI: embedded_hal::spi::SpiDevice,
+    BUSY: InputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+    // Runs the driver's reset and init sequence. `kind` must be resolved,
+    // `Auto` is handled by the caller through `detect`.
+    pub fn new(
+        kind: PanelKind,
+        spi: &mut SPI,
+        busy: BusyPin<BUSY>,
+        dc: DC,
+        rst: RST,
+    ) -> Result<Self, SPI::Error> {
+        let delay = &mut Ets;
+        Ok(match kind {
+            PanelKind::Auto | PanelKind::Epd2in9V2 => {
+                AnyEpd::Epd2in9V2(epd2in9_v2::Epd2in9::new(spi, busy, dc, rst, delay, None)?)
+            }
+            PanelKind::Epd2in9 => {
+                AnyEpd::Epd2in9(epd2in9::Epd2in9::new(spi, busy, dc, rst, delay, None)?)
+            }
+            PanelKind::Epd2in13V2 => {
+                AnyEpd::Epd2in13V2(epd2in13_v2::Epd2in13::new(spi, busy, dc, rst, delay, None)?)
+            }
+            PanelKind::Epd2in9bc => {
+                AnyEpd::Epd2in9bc(epd2in9bc::Epd2in9bc::new(spi, busy, dc, rst, delay, None)?)
+            }
+        })
+    }
+
+    // Native (unrotated) panel size in pixels, width is the short side.
+    pub fn size(&self) -> (u32, u32) {
+        dispatch!(self, epd => (epd.width(), epd.height()))
+    }
+
+    pub fn update_frame(&mut self, spi: &mut SPI, buffer: &[u8]) -> Result<(), SPI::Error> {
+        dispatch!(self, epd => epd.update_frame(spi, buffer, &mut Ets))
+    }
+
+    pub fn display_frame(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        dispatch!(self, epd => epd.display_frame(spi, &mut Ets))
+    }
+
+    pub fn sleep(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        dispatch!(self, epd => epd.sleep(spi, &mut Ets))
+    }
+
+    pub fn wake_up(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        dispatch!(self, epd => epd.wake_up(spi, &mut Ets))
+    }
+}
@@ -0,0 +1,239 @@
+use std::convert::Infallible;
+use std::ptr::{addr_of, addr_of_mut};
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::sys::*;
+use log::*;
+
+// Idle times after which the main loop drops into each sleep mode.
+#[derive(Clone, Copy, Debug)]
+pub struct PowerConfig {
+    // Light sleep keeps RAM, tasks and the radio state, wakes in about a ms.
+    pub light_sleep_after: Duration,
+    // Deep sleep only keeps RTC memory, the wake is a fresh boot.
+    pub deep_sleep_after: Duration,
+    // Timer wake from either sleep, for periodic work such as battery checks.
+    pub wake_interval: Duration,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            light_sleep_after: Duration::from_secs(5),
+            deep_sleep_after: Duration::from_secs(10 * 60),
+            wake_interval: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WakeCause {
+    // Cold boot or reset, not a wake from sleep.
+    PowerOn,
+    Timer,
+    Button,
+    // SX1262 DIO1, a packet came in.
+    Radio,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sleep {
+    Light,
+    Deep,
+}
+
+// Screen state kept across deep sleep. Plain data so it can live in RTC RAM;
+// settings and the inbox are in NVS already.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct RetainedState {
+    pub screen: u8,
+    pub index: u32,
+    pub line: u32,
+}
+
+#[repr(C)]
+struct RtcSlot {
+    magic: u32,
+    state: RetainedState,
+}
+
+const RTC_MAGIC: u32 = 0x7447_5231;
+
+// Zeroed on power-on, left alone by a deep sleep wake.
+#[link_section = ".rtc.data"]
+static mut RTC_SLOT: RtcSlot = RtcSlot {
+    magic: 0,
+    state: RetainedState {
+        screen: 0,
+        index: 0,
+        line: 0,
+    },
+};
+
+// Decides when to sleep and wires up the wake sources: the RTC timer, the
+// select button (active low) and the radio's DIO1 (active high).
+pub struct PowerManager {
+    config: PowerConfig,
+    button_pin: i32,
+    dio1_pin: i32,
+    last_activity: Instant,
+}
+
+impl PowerManager {
+    pub fn new(config: PowerConfig, button_pin: i32, dio1_pin: i32) -> Self {
+        Self {
+            config,
+            button_pin,
+            dio1_pin,
+            last_activity: Instant::now(),
+        }
+    }
+
+    // Why this boot happened, read once at startup.
+    pub fn boot_cause(&self) -> WakeCause {
+        match unsafe { esp_sleep_get_wakeup_cause() } {
+            esp_sleep_source_t_ESP_SLEEP_WAKEUP_UNDEFINED => WakeCause::PowerOn,
+            esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER => WakeCause::Timer,
+            esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT0 => WakeCause::Button,
+            esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1 => WakeCause::Radio,
+            _ => WakeCause::Other,
+        }
+    }
+
+    // Anything the user or the radio did pushes sleep back.
+    pub fn activity(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    // How long the main loop may block before calling `due`.
+    pub fn until_due(&self) -> Duration {
+        self.config
+            .light_sleep_after
+            .saturating_sub(self.last_activity.elapsed())
+    }
+
+    pub fn due(&self) -> Option<Sleep> {
+        let idle = self.last_activity.elapsed();
+        if idle >= self.config.deep_sleep_after {
+            Some(Sleep::Deep)
+        } else if idle >= self.config.light_sleep_after {
+            Some(Sleep::Light)
+        } else {
+            None
+        }
+    }
+
+    // Light sleep until the next wake interval, button press or packet. All
+    // tasks are frozen meanwhile and resume where they were.
+    pub fn light_sleep(&mut self) -> anyhow::Result<WakeCause> {
+        let remaining = self
+            .config
+            .deep_sleep_after
+            .saturating_sub(self.last_activity.elapsed());
+        let timeout = self.config.wake_interval.min(remaining);
+
+        unsafe {
+            esp_sleep_disable_wakeup_source(esp_sleep_source_t_ESP_SLEEP_WAKEUP_ALL);
+            esp!(esp_sleep_enable_timer_wakeup(timeout.as_micros() as u64))?;
+            esp!(gpio_wakeup_enable(
+                self.button_pin,
+                gpio_int_type_t_GPIO_INTR_LOW_LEVEL
+            ))?;
+            esp!(gpio_wakeup_enable(
+                self.dio1_pin,
+                gpio_int_type_t_GPIO_INTR_HIGH_LEVEL
+            ))?;
+            esp!(esp_sleep_enable_gpio_wakeup())?;
+        }
+
+        let result = esp!(unsafe { esp_light_sleep_start() });
+
+        // `gpio_wakeup_enable` replaced the pins' interrupt type with a level
+        // trigger, put back the edge trigger the button task relies on.
+        unsafe {
+            esp!(gpio_wakeup_disable(self.button_pin))?;
+            esp!(gpio_wakeup_disable(self.dio1_pin))?;
+            esp!(gpio_set_intr_type(
+                self.button_pin,
+                gpio_int_type_t_GPIO_INTR_ANYEDGE
+            ))?;
+        }
+        result?;
+
+        let cause = match unsafe { esp_sleep_get_wakeup_cause() } {
+            esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER => WakeCause::Timer,
+            // GPIO wake doesn't say which pin, the one still asserted does.
+            esp_sleep_source_t_ESP_SLEEP_WAKEUP_GPIO => {
+                if unsafe { gpio_get_level(self.button_pin) } == 0 {
+                    WakeCause::Button
+                } else if unsafe { gpio_get_level(self.dio1_pin) } != 0 {
+                    WakeCause::Radio
+                } else {
+                    WakeCause::Other
+                }
+            }
+            _ => WakeCause::Other,
+        };
+
+        if cause != WakeCause::Timer {
+            self.activity();
+        }
+        debug!("Light sleep wake: {:?}", cause);
+
+        Ok(cause)
+    }
+
+    // Saves `state` to RTC memory and deep sleeps until the next wake
+    // interval, a button press or a packet. The radio has to be listening
+    // already for DIO1 to fire; the packet that caused the wake stays in its
+    // buffer for `radio::take_pending` on the next boot. Only returns if a
+    // wake source can't be set up.
+    pub fn deep_sleep(&self, state: RetainedState) -> anyhow::Result<Infallible> {
+        retain(state);
+        info!("Deep sleep for up to {:?}", self.config.wake_interval);
+
+        unsafe {
+            esp_sleep_disable_wakeup_source(esp_sleep_source_t_ESP_SLEEP_WAKEUP_ALL);
+            esp!(esp_sleep_enable_timer_wakeup(
+                self.config.wake_interval.as_micros() as u64
+            ))?;
+            esp!(esp_sleep_enable_ext0_wakeup(self.button_pin, 0))?;
+            esp!(esp_sleep_enable_ext1_wakeup(
+                1 << self.dio1_pin,
+                esp_sleep_ext1_wakeup_mode_t_ESP_EXT1_WAKEUP_ANY_HIGH,
+            ))?;
+            esp_deep_sleep_start()
+        }
+    }
+
+    // Deep sleep with the button as the only wake source, for when running
+    // on is not safe. Nothing is retained, the wake is a fresh start.
+    pub fn shutdown(&self) -> anyhow::Result<Infallible> {
+        unsafe {
+            esp_sleep_disable_wakeup_source(esp_sleep_source_t_ESP_SLEEP_WAKEUP_ALL);
+            esp!(esp_sleep_enable_ext0_wakeup(self.button_pin, 0))?;
+            esp_deep_sleep_start()
+        }
+    }
+}
+
+fn retain(state: RetainedState) {
+    unsafe {
+        addr_of_mut!(RTC_SLOT).write(RtcSlot {
+            magic: RTC_MAGIC,
+            state,
+        })
+    };
+}
+
+// State saved by the last `deep_sleep`, if this boot is a wake from it.
+pub fn retained(cause: WakeCause) -> Option<RetainedState> {
+    if cause == WakeCause::PowerOn {
+        return None;
+    }
+
+    let slot = unsafe { addr_of!(RTC_SLOT).read() };
+    (slot.magic == RTC_MAGIC).then_some(slot.state)
+}
@@ -0,0 +1,154 @@
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use epd_waveshare::color::Color;
+use qrcodegen::{QrCode, QrCodeEcc};
+
+// Phones struggle below ~3px per module on the 2.9" panel, 2px is the hard floor.
+const MIN_MODULE_PX: u32 = 2;
+// The spec asks for 4 modules of quiet zone. The rest of the panel is white
+// anyway, so 2 is enough as long as nothing is drawn right next to the code.
+const QUIET_ZONE: u32 = 2;
+const CAPTION_GAP: i32 = 6;
+const CAPTION_LINE_HEIGHT: i32 = 12;
+
+// Identity shown to installers so a phone can pick up the device without typing.
+pub struct ProvisioningInfo<'a> {
+    pub node_id: &'a str,
+    pub key_fingerprint: &'a str,
+    pub url: &'a str,
+}
+
+impl ProvisioningInfo<'_> {
+    // Everything goes into the URL so scanning opens the provisioning page directly.
+    pub fn payload(&self) -> String {
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        format!(
+            "{}{}node={}&fp={}",
+            self.url,
+            separator,
+            percent_encode(self.node_id),
+            percent_encode(self.key_fingerprint)
+        )
+    }
+}
+
+// Query values keep RFC 3986's unreserved characters, everything else is
+// escaped byte by byte.
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// Encodes `text` and draws it as large as fits inside `area`, anchored to the
+// top left corner. Returns the rectangle actually covered.
+pub fn draw_qr<D>(target: &mut D, text: &str, area: Rectangle) -> anyhow::Result<Rectangle>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let qr = QrCode::encode_text(text, QrCodeEcc::Medium)
+        .map_err(|e| anyhow::anyhow!("QR encode failed: {:?}", e))?;
+
+    let modules = qr.size() as u32 + 2 * QUIET_ZONE;
+    let module_px = area.size.width.min(area.size.height) / modules;
+    if module_px < MIN_MODULE_PX {
+        return Err(anyhow::anyhow!(
+            "QR payload too long: {} modules do not fit in {}x{}px",
+            modules,
+            area.size.width,
+            area.size.height
+        ));
+    }
+
+    let side = modules * module_px;
+    let origin = area.top_left;
+    let bounds = Rectangle::new(origin, Size::new(side, side));
+
+    bounds
+        .into_styled(PrimitiveStyle::with_fill(Color::White))
+        .draw(target)
+        .map_err(|_| anyhow::anyhow!("QR draw failed"))?;
+
+    let black = PrimitiveStyle::with_fill(Color::Black);
+    let module = Size::new(module_px, module_px);
+    for y in 0..qr.size() {
+        for x in 0..qr.size() {
+            if !qr.get_module(x, y) {
+                continue;
+            }
+            let offset = Point::new(
+                (x as u32 + QUIET_ZONE) as i32 * module_px as i32,
+                (y as u32 + QUIET_ZONE) as i32 * module_px as i32,
+            );
+            Rectangle::new(origin + offset, module)
+                .into_styled(black)
+                .draw(target)
+                .map_err(|_| anyhow::anyhow!("QR draw failed"))?;
+        }
+    }
+
+    Ok(bounds)
+}
+
+// Draws the QR code and the human readable identity next to it, so installers
+// can still read it off if the scan fails. Landscape areas put the caption to
+// the right of the code, portrait areas below it.
+pub fn draw_provisioning<D>(
+    target: &mut D,
+    info: &ProvisioningInfo,
+    area: Rectangle,
+) -> anyhow::Result<()>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let portrait = area.size.height > area.size.width;
+
+    // Leave room below the code for the caption in portrait.
+    let qr_area = if portrait {
+        Rectangle::new(area.top_left, Size::new(area.size.width, area.size.width))
+    } else {
+        Rectangle::new(area.top_left, Size::new(area.size.height, area.size.height))
+    };
+    let qr = draw_qr(target, &info.payload(), qr_area)?;
+
+    let caption_origin = if portrait {
+        Point::new(
+            qr.top_left.x,
+            qr.top_left.y + qr.size.height as i32 + CAPTION_GAP,
+        )
+    } else {
+        Point::new(
+            qr.top_left.x + qr.size.width as i32 + CAPTION_GAP,
+            qr.top_left.y + CAPTION_LINE_HEIGHT,
+        )
+    };
+    let caption_width = (area.top_left.x + area.size.width as i32 - caption_origin.x).max(0) as u32;
+    let max_chars = (caption_width / FONT_6X10.character_size.width) as usize;
+
+    let style = MonoTextStyle::new(&FONT_6X10, Color::Black);
+    let node = format!("Node: {}", info.node_id);
+    let key = format!("Key:  {}", info.key_fingerprint);
+    let lines = ["Scan to provision", "", &node, &key, "", info.url];
+
+    let mut y = caption_origin.y;
+    for line in lines {
+        let line: String = line.chars().take(max_chars).collect();
+        Text::with_baseline(&line, Point::new(caption_origin.x, y), style, Baseline::Top)
+            .draw(target)
+            .map_err(|_| anyhow::anyhow!("Caption draw failed"))?;
+        y += CAPTION_LINE_HEIGHT;
+    }
+
+    Ok(())
+}
@@ -1,9 +1,102 @@
-// use esp_idf_hal::delay::Ets;
-use esp_idf_hal::gpio::*;
-use esp_idf_hal::timer::TimerDriver;
+use std::future::Future;
+use std::pin::pin;
+
+use embassy_futures::select::{select, Either};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::Operation;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+use log::*;
 use lora_phy::iv::GenericSx126xInterfaceVariant;
+use lora_phy::mod_params::PacketParams;
+use lora_phy::mod_traits::IrqState;
 use lora_phy::sx126x::{self, Sx1262, Sx126x};
 use lora_phy::LoRa;
+use tugger_core::spi::DeviceConfig;
+
+// Destination address that every node accepts.
+pub const BROADCAST: u32 = u32::MAX;
+// The SX1262 takes up to 16 MHz, mode 0.
+pub const SPI_CONFIG: DeviceConfig = DeviceConfig {
+    mode: embedded_hal::spi::MODE_0,
+    frequency_hz: 8_000_000,
+};
+
+// Largest payload the SX1262 FIFO takes in one packet.
+pub const MAX_PAYLOAD: usize = 255;
+
+// Over-the-air text message: [kind][to: u32 LE][from: u32 LE][UTF-8 text].
+#[derive(Clone, Debug)]
+pub struct TextPacket {
+    pub to: u32,
+    pub from: u32,
+    pub text: String,
+}
+
+impl TextPacket {
+    const KIND: u8 = 1;
+    const HEADER: usize = 9;
+
+    // Text that does not fit in one packet is cut at a character boundary.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut end = self.text.len().min(MAX_PAYLOAD - Self::HEADER);
+        while !self.text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let mut buf = Vec::with_capacity(Self::HEADER + end);
+        buf.push(Self::KIND);
+        buf.extend_from_slice(&self.to.to_le_bytes());
+        buf.extend_from_slice(&self.from.to_le_bytes());
+        buf.extend_from_slice(&self.text.as_bytes()[..end]);
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < Self::HEADER || buf[0] != Self::KIND {
+            return None;
+        }
+
+        Some(Self {
+            to: u32::from_le_bytes(buf[1..5].try_into().ok()?),
+            from: u32::from_le_bytes(buf[5..9].try_into().ok()?),
+            text: String::from_utf8_lossy(&buf[Self::HEADER..]).into_owned(),
+        })
+    }
+}
+
+// Position report, broadcast on every GNSS fix the node posts:
+// [kind][from: u32 LE][latitude: i32 LE][longitude: i32 LE][altitude: i16 LE]
+// [satellites]. Coordinates in 1e-7 degrees, altitude in metres with
+// `i16::MIN` for none.
+pub struct PositionPacket {
+    pub from: u32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude_m: Option<f32>,
+    pub satellites: u8,
+}
+
+impl PositionPacket {
+    const KIND: u8 = 2;
+    const LEN: usize = 16;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let degrees = |value: f64| (value * 1e7).round() as i32;
+        let altitude = self
+            .altitude_m
+            .map_or(i16::MIN, |m| m.round().clamp(-32767.0, 32767.0) as i16);
+
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.push(Self::KIND);
+        buf.extend_from_slice(&self.from.to_le_bytes());
+        buf.extend_from_slice(&degrees(self.latitude).to_le_bytes());
+        buf.extend_from_slice(&degrees(self.longitude).to_le_bytes());
+        buf.extend_from_slice(&altitude.to_le_bytes());
+        buf.push(self.satellites);
+        buf
+    }
+}
 
 pub struct RadioConfig {
     pub frequency: u32,
@@ -25,61 +118,177 @@ impl Default for RadioConfig {
     }
 }
 
-// Sx126x<SPI, BoardType, Delay>? Or SPI, InterfaceVariant.
-// lora-phy v3: Sx126x<SPI, IV, D>
-// IV must be the Type of the interface variant struct, not the enum value.
-// The type of `Sx126xVariant::Sx1262(...)` is `Sx126xVariant<PinDriver<...>, ...>`.
-// BUT, Sx126xVariant is an enum.
-// The error `expected type, found trait Sx126xVariant` suggests it might be a trait in v3 or I am using it wrong.
-// Actually, in v3, `Sx126xVariant` is an enum. You cannot use an enum variant as a type parameter unless it's a const generic?
-// NO. The second generic param of Sx126x is `IV`. IV must implement `InterfaceVariant`.
-// Does the enum `Sx126xVariant` implement `InterfaceVariant`? YES.
-// So `Sx126x<SPI, Sx126xVariant<'d, ...>, Ets>` SHOULD be correct if arguments match.
-// BUT `Sx126x` does NOT take lifetime `'d`.
-// My previous fix removed `'d` from `Sx126x`, but I kept it on `Sx126xVariant`.
-// `Sx126xVariant` DOES take `'d` because it holds `PinDriver<'d>`.
+// The SX1262 finishes any command well within this, see `TimedPin`.
+const BUSY_TIMEOUT_US: u32 = 100_000;
+
+#[derive(Debug)]
+pub enum TimedPinError<E> {
+    Pin(E),
+    Timeout,
+}
+
+impl<E: core::fmt::Debug> embedded_hal::digital::Error for TimedPinError<E> {
+    fn kind(&self) -> embedded_hal::digital::ErrorKind {
+        embedded_hal::digital::ErrorKind::Other
+    }
+}
+
+// An input whose waits give up after a limit. lora-phy waits on BUSY with no
+// bound, so a stuck line would hang the radio; with a limit the wait fails
+// and the driver reports `RadioError::Busy`. lora-phy takes BUSY and DIO1 as
+// one type, DIO1 goes without a limit since a receive waits as long as it
+// takes.
+pub struct TimedPin<P, D> {
+    pin: P,
+    // Delay source and the limit in µs.
+    limit: Option<(D, u32)>,
+}
 
-// Let's rely on type inference for the struct field to avoid this mess.
-// Use `Box<dyn RadioKind>`? No, overhead.
-// Use `impl RadioKind`? Can't in struct field.
-// We must name the type.
+impl<P: Wait, D: DelayNs> TimedPin<P, D> {
+    pub fn bounded(pin: P, delay: D, limit_us: u32) -> Self {
+        Self {
+            pin,
+            limit: Some((delay, limit_us)),
+        }
+    }
 
-pub struct TunggerRadio<'d, SPI>
+    pub fn unbounded(pin: P) -> Self {
+        Self { pin, limit: None }
+    }
+
+    async fn bound<F>(limit: &mut Option<(D, u32)>, wait: F) -> Result<(), TimedPinError<P::Error>>
+    where
+        F: core::future::Future<Output = Result<(), P::Error>>,
+    {
+        let Some((delay, us)) = limit else {
+            return wait.await.map_err(TimedPinError::Pin);
+        };
+        match select(wait, delay.delay_us(*us)).await {
+            Either::First(result) => result.map_err(TimedPinError::Pin),
+            Either::Second(()) => Err(TimedPinError::Timeout),
+        }
+    }
+}
+
+impl<P: Wait, D> embedded_hal::digital::ErrorType for TimedPin<P, D> {
+    type Error = TimedPinError<P::Error>;
+}
+
+impl<P: Wait, D: DelayNs> Wait for TimedPin<P, D> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        Self::bound(&mut self.limit, self.pin.wait_for_high()).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        Self::bound(&mut self.limit, self.pin.wait_for_low()).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        Self::bound(&mut self.limit, self.pin.wait_for_rising_edge()).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        Self::bound(&mut self.limit, self.pin.wait_for_falling_edge()).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        Self::bound(&mut self.limit, self.pin.wait_for_any_edge()).await
+    }
+}
+
+// SX126x commands and IRQ bits, from the datasheet.
+const SX126X_CLR_IRQ_STATUS: u8 = 0x02;
+const SX126X_GET_IRQ_STATUS: u8 = 0x12;
+const SX126X_GET_RX_BUFFER_STATUS: u8 = 0x13;
+const SX126X_READ_BUFFER: u8 = 0x1E;
+const IRQ_RX_DONE: u16 = 1 << 1;
+const IRQ_CRC_ERR: u16 = 1 << 6;
+
+// A deep sleep wake on DIO1 leaves the packet that raised it in the radio's
+// buffer, and driver init resets the chip. This reads it out first, then
+// clears the IRQ. `None` when the IRQ was something other than a good RxDone.
+pub async fn take_pending<SPI, WAIT, D>(
+    spi: &mut SPI,
+    busy: WAIT,
+    delay: D,
+) -> anyhow::Result<Option<Vec<u8>>>
+where
+    SPI: embedded_hal_async::spi::SpiDevice,
+    WAIT: Wait,
+    D: DelayNs,
+{
+    let busy = &mut TimedPin::bounded(busy, delay, BUSY_TIMEOUT_US);
+
+    let mut irq = [0u8; 2];
+    command(spi, busy, &[SX126X_GET_IRQ_STATUS, 0], &mut irq).await?;
+    let irq = u16::from_be_bytes(irq);
+
+    let mut packet = None;
+    if irq & IRQ_RX_DONE != 0 && irq & IRQ_CRC_ERR == 0 {
+        let mut status = [0u8; 2];
+        command(spi, busy, &[SX126X_GET_RX_BUFFER_STATUS, 0], &mut status).await?;
+        let [len, start] = status;
+        let mut payload = vec![0; len as usize];
+        command(spi, busy, &[SX126X_READ_BUFFER, start, 0], &mut payload).await?;
+        packet = Some(payload);
+    }
+
+    command(spi, busy, &[SX126X_CLR_IRQ_STATUS, 0xFF, 0xFF], &mut []).await?;
+    Ok(packet)
+}
+
+// Waits for BUSY low, then runs one command. The byte after the opcode
+// clocks out status, not data.
+async fn command<SPI: embedded_hal_async::spi::SpiDevice>(
+    spi: &mut SPI,
+    busy: &mut impl Wait,
+    write: &[u8],
+    read: &mut [u8],
+) -> anyhow::Result<()> {
+    busy.wait_for_low()
+        .await
+        .map_err(|_| anyhow::anyhow!("Radio busy stuck high"))?;
+    spi.transaction(&mut [Operation::Write(write), Operation::Read(read)])
+        .await
+        .map_err(|_| anyhow::anyhow!("Radio SPI transfer failed"))
+}
+
+// lora-phy 3 names the chip as Sx126x<SPI, InterfaceVariant, Variant> and
+// takes the pins through the interface variant.
+type Chip<SPI, CTRL, WAIT, BD> =
+    Sx126x<SPI, GenericSx126xInterfaceVariant<CTRL, TimedPin<WAIT, BD>>, Sx1262>;
+
+// Generic over the bus, pins and delays so tests can drive it through the
+// `spifault` wrappers. `BD` times out BUSY waits, `DELAY` is lora-phy's own.
+pub struct TunggerRadio<SPI, CTRL, WAIT, BD, DELAY>
 where
     SPI: embedded_hal_async::spi::SpiDevice,
+    CTRL: OutputPin,
+    WAIT: Wait,
+    BD: DelayNs,
+    DELAY: DelayNs,
 {
-    // Generics: <SPI, IV, Delay>
-    // IV: GenericSx126xInterfaceVariant<CTRL, WAIT>
-    // CTRL: PinDriver<'d, AnyOutputPin, Output>
-    // WAIT: PinDriver<'d, AnyInputPin, Input>
-    pub lora: LoRa<
-        Sx126x<
-            SPI,
-            GenericSx126xInterfaceVariant<
-                PinDriver<'d, AnyOutputPin, Output>,
-                PinDriver<'d, AnyInputPin, Input>,
-            >,
-            Sx1262,
-        >,
-        TimerDriver<'d>,
-    >,
-}
-
-impl<'d, SPI> TunggerRadio<'d, SPI>
+    pub lora: LoRa<Chip<SPI, CTRL, WAIT, BD>, DELAY>,
+    // Set by `listen`, `receive` reads packets with them.
+    rx_params: Option<PacketParams>,
+}
+
+impl<SPI, CTRL, WAIT, BD, DELAY> TunggerRadio<SPI, CTRL, WAIT, BD, DELAY>
 where
     SPI: embedded_hal_async::spi::SpiDevice,
+    CTRL: OutputPin,
+    WAIT: Wait,
+    BD: DelayNs,
+    DELAY: DelayNs,
 {
     pub async fn new(
         spi: SPI,
-        // We accept concrete pins but downgrade them inside, or expect AnyPin?
-        // Let's expect downgraded pins from main to keep signature simple here?
-        // Or coerce here. Let's coerce here if possible, but PinDriver coercion consumes.
-        // Better to ask caller to downgrade to avoid generic explosion.
-        nss: PinDriver<'d, AnyOutputPin, Output>,
-        rst: PinDriver<'d, AnyOutputPin, Output>,
-        busy: PinDriver<'d, AnyInputPin, Input>,
-        dio1: PinDriver<'d, AnyInputPin, Input>,
-        delay: TimerDriver<'d>,
+        // NSS is owned by `spi`, which asserts it for each transaction.
+        rst: CTRL,
+        busy: WAIT,
+        dio1: WAIT,
+        busy_delay: BD,
+        delay: DELAY,
     ) -> anyhow::Result<Self> {
         let config = sx126x::Config {
             chip: Sx1262,
@@ -88,9 +297,11 @@ where
             rx_boost: false,
         };
 
-        // Note: GenericSx126xInterfaceVariant::new signature:
-        // new(nss, reset, dio1, busy, ant_sw)
-        let iv = GenericSx126xInterfaceVariant::new(nss, rst, dio1, busy, None)
+        // lora-phy 3: new(reset, dio1, busy, rf_switch_rx, rf_switch_tx). The
+        // Heltec boards switch the antenna from DIO2, so no switch pins.
+        let busy = TimedPin::bounded(busy, busy_delay, BUSY_TIMEOUT_US);
+        let dio1 = TimedPin::unbounded(dio1);
+        let iv = GenericSx126xInterfaceVariant::new(rst, dio1, busy, None, None)
             .map_err(|e| anyhow::anyhow!("IV init failed: {:?}", e))?;
 
         // Construct Sx1262 directly
@@ -100,7 +311,10 @@ where
             .await
             .map_err(|e| anyhow::anyhow!("LoRa init failed: {:?}", e))?;
 
-        Ok(Self { lora })
+        Ok(Self {
+            lora,
+            rx_params: None,
+        })
     }
 
     pub async fn configure(&mut self, cfg: &RadioConfig) -> anyhow::Result<()> {
@@ -157,4 +371,257 @@ where
 
         Ok(())
     }
+
+    // Continuous receive. The SX1262 raises DIO1 on RxDone and keeps
+    // listening while the MCU sleeps, which is what lets a packet wake it.
+    pub async fn listen(&mut self, cfg: &RadioConfig) -> anyhow::Result<()> {
+        let mdltn_params = self
+            .lora
+            .create_modulation_params(
+                lora_phy::mod_params::SpreadingFactor::_9,
+                lora_phy::mod_params::Bandwidth::_125KHz,
+                lora_phy::mod_params::CodingRate::_4_7,
+                cfg.frequency,
+            )
+            .map_err(|e| anyhow::anyhow!("ModParams error: {:?}", e))?;
+
+        let rx_pkt_params = self
+            .lora
+            .create_rx_packet_params(8, false, MAX_PAYLOAD as u8, true, false, &mdltn_params)
+            .map_err(|e| anyhow::anyhow!("RxParams error: {:?}", e))?;
+
+        self.lora
+            .prepare_for_rx(lora_phy::RxMode::Continuous, &mdltn_params, &rx_pkt_params)
+            .await
+            .map_err(|e| anyhow::anyhow!("PrepareRx error: {:?}", e))?;
+
+        self.lora
+            .start_rx()
+            .await
+            .map_err(|e| anyhow::anyhow!("StartRx error: {:?}", e))?;
+
+        self.rx_params = Some(rx_pkt_params);
+        Ok(())
+    }
+
+    // Resolves once DIO1 is raised. Only waits on the pin, so it can be
+    // dropped when something else wins a select.
+    pub async fn wait_for_irq(&mut self) -> anyhow::Result<()> {
+        self.lora
+            .wait_for_irq()
+            .await
+            .map_err(|e| anyhow::anyhow!("IRQ wait error: {:?}", e))
+    }
+
+    // Reads and clears the IRQ status after `wait_for_irq`, returning the
+    // packet's length in `buf` and its RSSI in dBm on RxDone. The radio keeps
+    // listening.
+    pub async fn receive(
+        &mut self,
+        buf: &mut [u8; MAX_PAYLOAD],
+    ) -> anyhow::Result<Option<(usize, i16)>> {
+        let Some(rx_params) = &self.rx_params else {
+            anyhow::bail!("Receive without listen");
+        };
+        let irq = self
+            .lora
+            .process_irq_event()
+            .await
+            .map_err(|e| anyhow::anyhow!("IRQ error: {:?}", e))?;
+        if !matches!(irq, Some(IrqState::Done)) {
+            return Ok(None);
+        }
+
+        let (len, status) = self
+            .lora
+            .get_rx_result(rx_params, buf)
+            .await
+            .map_err(|e| anyhow::anyhow!("RX error: {:?}", e))?;
+        debug!(
+            "Received {} bytes, RSSI {} dBm, SNR {} dB",
+            len, status.rssi, status.snr
+        );
+        Ok(Some((len as usize, status.rssi)))
+    }
+
+    // Collects packets and their RSSI until `until` completes, for while the
+    // main loop waits on something else. Only the DIO1 wait is ever cut
+    // short, never a transfer, so the bus is free once it returns.
+    pub async fn receive_until(
+        &mut self,
+        until: impl Future<Output = ()>,
+        packets: &mut Vec<(Vec<u8>, i16)>,
+    ) -> anyhow::Result<()> {
+        let mut until = pin!(until);
+        let mut buf = [0u8; MAX_PAYLOAD];
+        loop {
+            match select(self.wait_for_irq(), until.as_mut()).await {
+                Either::First(irq) => irq?,
+                Either::Second(()) => return Ok(()),
+            }
+            match self.receive(&mut buf).await {
+                Ok(Some((len, rssi))) => packets.push((buf[..len].to_vec(), rssi)),
+                Ok(None) => {}
+                Err(e) => warn!("Receive failed: {:?}", e),
+            }
+        }
+    }
+
+    pub async fn send_text(&mut self, packet: &TextPacket) -> anyhow::Result<()> {
+        self.transmit(&packet.encode()).await
+    }
+
+    pub async fn send_position(&mut self, packet: &PositionPacket) -> anyhow::Result<()> {
+        self.transmit(&packet.encode()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use embassy_futures::block_on;
+    use embedded_hal::spi::ErrorKind;
+    use tugger_core::spifault::{
+        Fault, FaultyDevice, FixedPin, NullDevice, Schedule, StuckPin, When,
+    };
+
+    use super::*;
+
+    // Both lora-phy's delays and the BUSY limit run out at once.
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    // Brings the radio up on a bus and busy line broken as scheduled. DIO1
+    // reads high, so nothing waits on it.
+    fn init(spi: Schedule<Fault>, busy: Schedule<bool>) -> anyhow::Result<()> {
+        let spi = FaultyDevice::new(NullDevice, spi);
+        let busy = StuckPin::new(FixedPin(false), busy);
+        let dio1 = StuckPin::new(FixedPin(true), Schedule::new());
+        let radio = TunggerRadio::new(spi, FixedPin(true), busy, dio1, NoDelay, NoDelay);
+        block_on(radio).map(|_| ())
+    }
+
+    #[test]
+    fn stuck_busy_times_out() {
+        let e = init(Schedule::new(), Schedule::new().with(When::From(0), true)).unwrap_err();
+        assert_eq!(e.to_string(), "LoRa init failed: Busy");
+    }
+
+    #[test]
+    fn nak_fails_init() {
+        let nak = Schedule::new().with(When::From(0), Fault::Error(ErrorKind::Other));
+        let e = init(nak, Schedule::new()).unwrap_err();
+        assert_eq!(e.to_string(), "LoRa init failed: SPI");
+    }
+
+    #[test]
+    fn short_transfer_fails_init() {
+        // Every command is at least two bytes.
+        let short = Schedule::new().with(When::From(0), Fault::Short(1));
+        let e = init(short, Schedule::new()).unwrap_err();
+        assert_eq!(e.to_string(), "LoRa init failed: SPI");
+    }
+
+    // Answers `take_pending` like an SX1262 holding `payload` at buffer
+    // offset 0x80.
+    struct PendingRx {
+        irq: u16,
+        payload: Vec<u8>,
+        cleared: bool,
+    }
+
+    impl embedded_hal::spi::ErrorType for PendingRx {
+        type Error = Infallible;
+    }
+
+    impl embedded_hal_async::spi::SpiDevice for PendingRx {
+        async fn transaction(
+            &mut self,
+            operations: &mut [Operation<'_, u8>],
+        ) -> Result<(), Infallible> {
+            let [Operation::Write(command), Operation::Read(read)] = operations else {
+                panic!("unexpected transaction");
+            };
+            match command[0] {
+                SX126X_GET_IRQ_STATUS => read.copy_from_slice(&self.irq.to_be_bytes()),
+                SX126X_GET_RX_BUFFER_STATUS => {
+                    read.copy_from_slice(&[self.payload.len() as u8, 0x80])
+                }
+                SX126X_READ_BUFFER => {
+                    assert_eq!(command[1], 0x80);
+                    read.copy_from_slice(&self.payload);
+                }
+                SX126X_CLR_IRQ_STATUS => self.cleared = true,
+                other => panic!("unexpected command {:#04x}", other),
+            }
+            Ok(())
+        }
+    }
+
+    fn take(irq: u16, payload: &[u8]) -> (Option<Vec<u8>>, bool) {
+        let mut spi = PendingRx {
+            irq,
+            payload: payload.to_vec(),
+            cleared: false,
+        };
+        let packet = block_on(take_pending(&mut spi, FixedPin(false), NoDelay)).unwrap();
+        (packet, spi.cleared)
+    }
+
+    #[test]
+    fn pending_packet_is_read_and_cleared() {
+        let text = TextPacket {
+            to: BROADCAST,
+            from: 7,
+            text: "Hi".into(),
+        }
+        .encode();
+        assert_eq!(take(IRQ_RX_DONE, &text), (Some(text), true));
+    }
+
+    #[test]
+    fn pending_crc_error_is_dropped() {
+        assert_eq!(take(IRQ_RX_DONE | IRQ_CRC_ERR, &[1, 2, 3]), (None, true));
+        assert_eq!(take(0, &[]), (None, true));
+    }
+
+    #[test]
+    fn stuck_busy_fails_take_pending() {
+        let mut spi = PendingRx {
+            irq: IRQ_RX_DONE,
+            payload: vec![1],
+            cleared: false,
+        };
+        let busy = StuckPin::new(FixedPin(false), Schedule::new().with(When::From(0), true));
+        let e = block_on(take_pending(&mut spi, busy, NoDelay)).unwrap_err();
+        assert_eq!(e.to_string(), "Radio busy stuck high");
+    }
+
+    #[test]
+    fn position_packet_layout() {
+        let packet = PositionPacket {
+            from: 0x0403_0201,
+            latitude: 48.117302,
+            longitude: -11.5166707,
+            altitude_m: Some(519.4),
+            satellites: 7,
+        };
+        let buf = packet.encode();
+        assert_eq!(buf.len(), PositionPacket::LEN);
+        assert_eq!(buf[..5], [2, 1, 2, 3, 4]);
+        assert_eq!(buf[5..9], 481_173_020i32.to_le_bytes());
+        assert_eq!(buf[9..13], (-115_166_707i32).to_le_bytes());
+        assert_eq!(buf[13..15], 519i16.to_le_bytes());
+        assert_eq!(buf[15], 7);
+
+        let unknown = PositionPacket {
+            altitude_m: None,
+            ..packet
+        };
+        assert_eq!(unknown.encode()[13..15], i16::MIN.to_le_bytes());
+    }
 }
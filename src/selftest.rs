@@ -0,0 +1,298 @@
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use embedded_graphics::prelude::*;
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::spi::Operation;
+use epd_waveshare::color::Color;
+use esp_idf_hal::adc::Adc;
+use esp_idf_hal::sys::*;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use log::*;
+
+use crate::battery::BatteryAdc;
+use crate::ui;
+
+// A charged or partly charged cell. Outside of it the divider, the ADC or the
+// cell is bad.
+const BATTERY_RANGE_MV: RangeInclusive<u32> = 3000..=4350;
+// The SX1262 finishes any command well within this.
+const RADIO_BUSY_TIMEOUT: Duration = Duration::from_millis(100);
+
+// SX126x commands and registers, from the datasheet.
+const SX126X_WRITE_REGISTER: u8 = 0x0D;
+const SX126X_READ_REGISTER: u8 = 0x1D;
+// 16 bytes of ASCII, e.g. "SX1261 V2D 2D02".
+const SX126X_REG_VERSION: u16 = 0x0320;
+// LoRa sync word, two bytes, rewritten by the driver's configure anyway.
+const SX126X_REG_SYNC_WORD: u16 = 0x0740;
+
+const NVS_NAMESPACE: &str = "selftest";
+
+// Select is GPIO0, a strapping pin: held through power-on it starts the ROM
+// bootloader instead of us. The self-test is asked for by pressing select
+// just after power-on instead, and holding it for `REQUEST_HOLD`.
+const REQUEST_HOLD: Duration = Duration::from_secs(1);
+const REQUEST_POLL: Duration = Duration::from_millis(10);
+
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+// Results of the boot self-test, for the manufacturing line.
+#[derive(Default)]
+pub struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    // `Ok` carries what was measured, `Err` why the check failed.
+    pub fn record(&mut self, name: &'static str, result: anyhow::Result<String>) {
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, e.to_string()),
+        };
+        self.checks.push(Check {
+            name,
+            passed,
+            detail,
+        });
+    }
+
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+
+    pub fn log(&self) {
+        for check in &self.checks {
+            if check.passed {
+                info!("Self-test {}: PASS ({})", check.name, check.detail);
+            } else {
+                error!("Self-test {}: FAIL ({})", check.name, check.detail);
+            }
+        }
+        info!(
+            "Self-test {}",
+            if self.passed() { "passed" } else { "FAILED" }
+        );
+    }
+
+    pub fn draw<D>(&self, target: &mut D) -> anyhow::Result<()>
+    where
+        D: DrawTarget<Color = Color>,
+    {
+        let verdict = if self.passed() { "PASS" } else { "FAIL" };
+        let lines: Vec<String> = self
+            .checks
+            .iter()
+            .map(|c| {
+                let mark = if c.passed { "ok" } else { "FAIL" };
+                format!("{} {}: {}", mark, c.name, c.detail)
+            })
+            .collect();
+
+        ui::draw_header(target, "Self-test", verdict)
+            .and_then(|_| ui::draw_lines(target, &lines))
+            .map_err(|_| anyhow::anyhow!("Self-test report draw failed"))
+    }
+}
+
+// Whether select (active low) is down now and stays down for `REQUEST_HOLD`.
+// A button that is up, or can't be read, costs the boot nothing.
+pub fn requested<P: InputPin>(button: &mut P) -> bool {
+    if !button.is_low().unwrap_or(false) {
+        return false;
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < REQUEST_HOLD {
+        if !button.is_low().unwrap_or(false) {
+            return false;
+        }
+        std::thread::sleep(REQUEST_POLL);
+    }
+    true
+}
+
+// Resets the SX1262, reads its version string and checks that a register
+// write reads back. Runs before the radio driver takes the chip over.
+pub async fn radio<SPI, RST, BUSY>(
+    spi: &mut SPI,
+    rst: &mut RST,
+    busy: &mut BUSY,
+) -> anyhow::Result<String>
+where
+    SPI: embedded_hal_async::spi::SpiDevice,
+    RST: OutputPin,
+    BUSY: InputPin,
+{
+    rst.set_low()
+        .map_err(|_| anyhow::anyhow!("Radio reset failed"))?;
+    std::thread::sleep(Duration::from_millis(1));
+    rst.set_high()
+        .map_err(|_| anyhow::anyhow!("Radio reset failed"))?;
+
+    let mut version = [0u8; 16];
+    read_register(spi, busy, SX126X_REG_VERSION, &mut version).await?;
+    let version = String::from_utf8_lossy(&version)
+        .trim_end_matches('\0')
+        .to_string();
+    if !version.starts_with("SX126") {
+        anyhow::bail!("Unexpected version {:02x?}", version.as_bytes());
+    }
+
+    let mut original = [0u8; 2];
+    read_register(spi, busy, SX126X_REG_SYNC_WORD, &mut original).await?;
+    let pattern = [0x5A, 0xA5];
+    write_register(spi, busy, SX126X_REG_SYNC_WORD, &pattern).await?;
+    let mut readback = [0u8; 2];
+    read_register(spi, busy, SX126X_REG_SYNC_WORD, &mut readback).await?;
+    write_register(spi, busy, SX126X_REG_SYNC_WORD, &original).await?;
+    if readback != pattern {
+        anyhow::bail!(
+            "Register readback {:02x?}, wrote {:02x?}",
+            readback,
+            pattern
+        );
+    }
+
+    Ok(version)
+}
+
+// BUSY high means the chip can't take a command yet.
+fn wait_radio_ready(busy: &mut impl InputPin) -> anyhow::Result<()> {
+    let start = Instant::now();
+    while busy
+        .is_high()
+        .map_err(|_| anyhow::anyhow!("Radio busy read failed"))?
+    {
+        if start.elapsed() > RADIO_BUSY_TIMEOUT {
+            anyhow::bail!("Radio busy stuck high");
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    Ok(())
+}
+
+async fn read_register<SPI>(
+    spi: &mut SPI,
+    busy: &mut impl InputPin,
+    address: u16,
+    data: &mut [u8],
+) -> anyhow::Result<()>
+where
+    SPI: embedded_hal_async::spi::SpiDevice,
+{
+    wait_radio_ready(busy)?;
+    let [hi, lo] = address.to_be_bytes();
+    // The byte after the address clocks out status, not data.
+    spi.transaction(&mut [
+        Operation::Write(&[SX126X_READ_REGISTER, hi, lo, 0]),
+        Operation::Read(data),
+    ])
+    .await
+    .map_err(|_| anyhow::anyhow!("Radio SPI read failed"))
+}
+
+async fn write_register<SPI>(
+    spi: &mut SPI,
+    busy: &mut impl InputPin,
+    address: u16,
+    data: &[u8],
+) -> anyhow::Result<()>
+where
+    SPI: embedded_hal_async::spi::SpiDevice,
+{
+    wait_radio_ready(busy)?;
+    let [hi, lo] = address.to_be_bytes();
+    spi.transaction(&mut [
+        Operation::Write(&[SX126X_WRITE_REGISTER, hi, lo]),
+        Operation::Write(data),
+    ])
+    .await
+    .map_err(|_| anyhow::anyhow!("Radio SPI write failed"))
+}
+
+pub fn battery<A: Adc>(adc: &mut BatteryAdc<A>) -> anyhow::Result<String> {
+    let mv = adc.read_millivolts()?;
+    if !BATTERY_RANGE_MV.contains(&mv) {
+        anyhow::bail!(
+            "{} mV, expected {}-{} mV",
+            mv,
+            BATTERY_RANGE_MV.start(),
+            BATTERY_RANGE_MV.end()
+        );
+    }
+    Ok(format!("{} mV", mv))
+}
+
+// Flash size as configured, then a write, read back and erase through NVS.
+pub fn storage(partition: EspDefaultNvsPartition) -> anyhow::Result<String> {
+    let mut size = 0;
+    esp!(unsafe { esp_flash_get_size(std::ptr::null_mut(), &mut size) })?;
+
+    let mut nvs = EspNvs::new(partition, NVS_NAMESPACE, true)?;
+    let pattern = 0x5AA5_C33C ^ size;
+    nvs.set_u32("probe", pattern)?;
+    let readback = nvs.get_u32("probe")?;
+    nvs.remove("probe")?;
+    if readback != Some(pattern) {
+        anyhow::bail!("NVS readback {:x?}, wrote {:x}", readback, pattern);
+    }
+
+    Ok(format!("{} MB flash, NVS ok", size / (1024 * 1024)))
+}
+
+// Boards without PSRAM pass, one that is fitted has to hold a pattern.
+pub fn psram() -> anyhow::Result<String> {
+    let total = unsafe { heap_caps_get_total_size(MALLOC_CAP_SPIRAM) };
+    if total == 0 {
+        return Ok("none fitted".to_string());
+    }
+
+    const LEN: usize = 64 * 1024;
+    let ptr = unsafe { heap_caps_malloc(LEN, MALLOC_CAP_SPIRAM) } as *mut u8;
+    if ptr.is_null() {
+        anyhow::bail!("{} KB fitted, allocation failed", total / 1024);
+    }
+
+    let buf = unsafe { std::slice::from_raw_parts_mut(ptr, LEN) };
+    buf.iter_mut()
+        .enumerate()
+        .for_each(|(i, b)| *b = (i ^ (i >> 8)) as u8);
+    let intact = buf
+        .iter()
+        .enumerate()
+        .all(|(i, b)| *b == (i ^ (i >> 8)) as u8);
+    unsafe { heap_caps_free(ptr.cast()) };
+
+    if !intact {
+        anyhow::bail!("{} KB fitted, pattern mismatch", total / 1024);
+    }
+    Ok(format!("{} KB", total / 1024))
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use tugger_core::spifault::FixedPin;
+    use tugger_core::spitrace::{parse_dump, Replay};
+
+    // A radio self-test captured on a Wireless Paper V1.1, with the display
+    // traffic around it.
+    const SELFTEST_RADIO: &str = include_str!("../tugger-core/tests/fixtures/selftest-radio.log");
+
+    #[test]
+    fn replays_selftest_radio() {
+        let records = parse_dump(SELFTEST_RADIO).unwrap();
+        let mut spi = Replay::new(records, "radio");
+        // Busy always reads idle.
+        let (mut rst, mut busy) = (FixedPin(false), FixedPin(false));
+        let version = block_on(super::radio(&mut spi, &mut rst, &mut busy)).unwrap();
+
+        assert_eq!(version, "SX1261 V2D 2D02");
+        assert!(spi.finished());
+    }
+}
@@ -0,0 +1,506 @@
+use std::time::Duration;
+
+use embedded_hal::i2c::I2c;
+use log::*;
+
+use crate::event::{Event, Sender};
+use crate::hardware::PowerRail;
+
+// How often every sensor is read.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reading {
+    Climate {
+        temperature_c: f32,
+        humidity_pct: f32,
+        // Only from sensors with a barometer.
+        pressure_hpa: Option<f32>,
+    },
+    // Acceleration in g, including gravity.
+    Motion {
+        x: f32,
+        y: f32,
+        z: f32,
+    },
+}
+
+impl std::fmt::Display for Reading {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reading::Climate {
+                temperature_c,
+                humidity_pct,
+                pressure_hpa,
+            } => {
+                write!(f, "{:.1} °C, {:.0} %RH", temperature_c, humidity_pct)?;
+                match pressure_hpa {
+                    Some(hpa) => write!(f, ", {:.1} hPa", hpa),
+                    None => Ok(()),
+                }
+            }
+            Reading::Motion { x, y, z } => write!(f, "{:.2}/{:.2}/{:.2} g", x, y, z),
+        }
+    }
+}
+
+// One reading and where it came from, for the telemetry pipeline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub sensor: &'static str,
+    pub address: u8,
+    pub reading: Reading,
+}
+
+// A driver for one part on the expansion bus. The bus may have been powered
+// down since the last call, so `read` sets the part up again if it needs to.
+pub trait Sensor<I: I2c>: Send {
+    fn name(&self) -> &'static str;
+    fn address(&self) -> u8;
+    fn read(&mut self, i2c: &mut I) -> anyhow::Result<Reading>;
+}
+
+fn bus_error<E: core::fmt::Debug>(e: E) -> anyhow::Error {
+    anyhow::anyhow!("I2C error: {:?}", e)
+}
+
+// CRC-8 of the Sensirion parts: polynomial 0x31, init 0xFF.
+fn sensirion_crc(data: &[u8]) -> u8 {
+    data.iter().fold(0xFF, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+// Two CRC protected words: [msb, lsb, crc, msb, lsb, crc].
+fn sensirion_words(buf: &[u8; 6]) -> anyhow::Result<(u16, u16)> {
+    for word in buf.chunks(3) {
+        if sensirion_crc(&word[..2]) != word[2] {
+            anyhow::bail!("CRC mismatch");
+        }
+    }
+    Ok((
+        u16::from_be_bytes([buf[0], buf[1]]),
+        u16::from_be_bytes([buf[3], buf[4]]),
+    ))
+}
+
+fn sensirion_temperature(raw: u16) -> f32 {
+    -45.0 + 175.0 * raw as f32 / 65535.0
+}
+
+// Sensirion SHT3x (SHT30/31/35).
+pub struct Sht3x {
+    address: u8,
+}
+
+impl Sht3x {
+    const READ_STATUS: [u8; 2] = [0xF3, 0x2D];
+    // Single shot, high repeatability, no clock stretching.
+    const MEASURE: [u8; 2] = [0x24, 0x00];
+    const MEASURE_TIME: Duration = Duration::from_millis(16);
+
+    // Only the SHT3x answers the two-byte status command with a valid CRC.
+    fn probe<I: I2c>(i2c: &mut I, address: u8) -> bool {
+        let mut status = [0u8; 3];
+        i2c.write_read(address, &Self::READ_STATUS, &mut status)
+            .is_ok()
+            && sensirion_crc(&status[..2]) == status[2]
+    }
+}
+
+impl<I: I2c> Sensor<I> for Sht3x {
+    fn name(&self) -> &'static str {
+        "SHT3x"
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn read(&mut self, i2c: &mut I) -> anyhow::Result<Reading> {
+        i2c.write(self.address, &Self::MEASURE).map_err(bus_error)?;
+        std::thread::sleep(Self::MEASURE_TIME);
+        let mut buf = [0u8; 6];
+        i2c.read(self.address, &mut buf).map_err(bus_error)?;
+
+        let (t, rh) = sensirion_words(&buf)?;
+        Ok(Reading::Climate {
+            temperature_c: sensirion_temperature(t),
+            humidity_pct: 100.0 * rh as f32 / 65535.0,
+            pressure_hpa: None,
+        })
+    }
+}
+
+// Sensirion SHT4x (SHT40/41/45), same addresses as the SHT3x but one-byte
+// commands.
+pub struct Sht4x {
+    address: u8,
+}
+
+impl Sht4x {
+    const READ_SERIAL: u8 = 0x89;
+    // High precision measurement.
+    const MEASURE: u8 = 0xFD;
+    const MEASURE_TIME: Duration = Duration::from_millis(10);
+
+    fn probe<I: I2c>(i2c: &mut I, address: u8) -> bool {
+        let mut serial = [0u8; 6];
+        if i2c.write(address, &[Self::READ_SERIAL]).is_err() {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+        i2c.read(address, &mut serial).is_ok() && sensirion_words(&serial).is_ok()
+    }
+}
+
+impl<I: I2c> Sensor<I> for Sht4x {
+    fn name(&self) -> &'static str {
+        "SHT4x"
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn read(&mut self, i2c: &mut I) -> anyhow::Result<Reading> {
+        i2c.write(self.address, &[Self::MEASURE])
+            .map_err(bus_error)?;
+        std::thread::sleep(Self::MEASURE_TIME);
+        let mut buf = [0u8; 6];
+        i2c.read(self.address, &mut buf).map_err(bus_error)?;
+
+        let (t, rh) = sensirion_words(&buf)?;
+        Ok(Reading::Climate {
+            temperature_c: sensirion_temperature(t),
+            humidity_pct: (-6.0 + 125.0 * rh as f32 / 65535.0).clamp(0.0, 100.0),
+            pressure_hpa: None,
+        })
+    }
+}
+
+// Trimming values burnt into the BME280, see the datasheet section 4.2.2.
+#[derive(Clone, Copy, Debug, Default)]
+struct Bme280Calibration {
+    t1: f64,
+    t2: f64,
+    t3: f64,
+    p: [f64; 9],
+    h1: f64,
+    h2: f64,
+    h3: f64,
+    h4: f64,
+    h5: f64,
+    h6: f64,
+}
+
+impl Bme280Calibration {
+    // `a` is registers 0x88..=0xA1, `b` is 0xE1..=0xE7.
+    fn parse(a: &[u8; 26], b: &[u8; 7]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([a[i], a[i + 1]]) as f64;
+        let i16_at = |i: usize| i16::from_le_bytes([a[i], a[i + 1]]) as f64;
+
+        let mut p = [0.0; 9];
+        p[0] = u16_at(6);
+        for (n, p) in p.iter_mut().enumerate().skip(1) {
+            *p = i16_at(6 + 2 * n);
+        }
+
+        Self {
+            t1: u16_at(0),
+            t2: i16_at(2),
+            t3: i16_at(4),
+            p,
+            h1: a[25] as f64,
+            h2: i16::from_le_bytes([b[0], b[1]]) as f64,
+            h3: b[2] as f64,
+            // 12-bit signed values packed around a shared nibble byte.
+            h4: (((b[3] as i8 as i16) << 4) | (b[4] & 0x0F) as i16) as f64,
+            h5: (((b[5] as i8 as i16) << 4) | (b[4] >> 4) as i16) as f64,
+            h6: b[6] as i8 as f64,
+        }
+    }
+
+    // Datasheet floating point compensation, section 8.1. Returns °C, Pa
+    // and %RH.
+    fn compensate(&self, adc_t: u32, adc_p: u32, adc_h: u32) -> (f64, f64, f64) {
+        let (adc_t, adc_p, adc_h) = (adc_t as f64, adc_p as f64, adc_h as f64);
+
+        let var1 = (adc_t / 16384.0 - self.t1 / 1024.0) * self.t2;
+        let var2 = (adc_t / 131072.0 - self.t1 / 8192.0).powi(2) * self.t3;
+        let t_fine = var1 + var2;
+        let temperature = t_fine / 5120.0;
+
+        let p = &self.p;
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * p[5] / 32768.0;
+        var2 += var1 * p[4] * 2.0;
+        var2 = var2 / 4.0 + p[3] * 65536.0;
+        var1 = (p[2] * var1 * var1 / 524288.0 + p[1] * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * p[0];
+        let pressure = if var1 == 0.0 {
+            0.0
+        } else {
+            let mut pressure = 1048576.0 - adc_p;
+            pressure = (pressure - var2 / 4096.0) * 6250.0 / var1;
+            let var1 = p[8] * pressure * pressure / 2147483648.0;
+            let var2 = pressure * p[7] / 32768.0;
+            pressure + (var1 + var2 + p[6]) / 16.0
+        };
+
+        let mut h = t_fine - 76800.0;
+        h = (adc_h - (self.h4 * 64.0 + self.h5 / 16384.0 * h))
+            * (self.h2 / 65536.0
+                * (1.0 + self.h6 / 67108864.0 * h * (1.0 + self.h3 / 67108864.0 * h)));
+        h *= 1.0 - self.h1 * h / 524288.0;
+        let humidity = h.clamp(0.0, 100.0);
+
+        (temperature, pressure, humidity)
+    }
+}
+
+// Bosch BME280, temperature, humidity and pressure in forced mode: one
+// conversion per read, asleep in between.
+pub struct Bme280 {
+    address: u8,
+    calibration: Option<Bme280Calibration>,
+}
+
+impl Bme280 {
+    const REG_ID: u8 = 0xD0;
+    const CHIP_ID: u8 = 0x60;
+    const REG_CALIB_A: u8 = 0x88;
+    const REG_CALIB_B: u8 = 0xE1;
+    const REG_CTRL_HUM: u8 = 0xF2;
+    const REG_STATUS: u8 = 0xF3;
+    const REG_CTRL_MEAS: u8 = 0xF4;
+    const REG_DATA: u8 = 0xF7;
+    // Oversampling x1 for all three.
+    const CTRL_HUM: u8 = 0b001;
+    // osrs_t x1, osrs_p x1, forced mode.
+    const CTRL_MEAS: u8 = 0b001_001_01;
+    const STATUS_MEASURING: u8 = 1 << 3;
+
+    fn probe<I: I2c>(i2c: &mut I, address: u8) -> bool {
+        let mut id = [0u8];
+        i2c.write_read(address, &[Self::REG_ID], &mut id).is_ok() && id[0] == Self::CHIP_ID
+    }
+
+    fn calibration<I: I2c>(&mut self, i2c: &mut I) -> anyhow::Result<Bme280Calibration> {
+        if let Some(calibration) = self.calibration {
+            return Ok(calibration);
+        }
+
+        let mut a = [0u8; 26];
+        let mut b = [0u8; 7];
+        i2c.write_read(self.address, &[Self::REG_CALIB_A], &mut a)
+            .map_err(bus_error)?;
+        i2c.write_read(self.address, &[Self::REG_CALIB_B], &mut b)
+            .map_err(bus_error)?;
+        let calibration = Bme280Calibration::parse(&a, &b);
+        self.calibration = Some(calibration);
+        Ok(calibration)
+    }
+}
+
+impl<I: I2c> Sensor<I> for Bme280 {
+    fn name(&self) -> &'static str {
+        "BME280"
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn read(&mut self, i2c: &mut I) -> anyhow::Result<Reading> {
+        let calibration = self.calibration(i2c)?;
+
+        // ctrl_hum only takes effect with the following ctrl_meas write.
+        i2c.write(self.address, &[Self::REG_CTRL_HUM, Self::CTRL_HUM])
+            .map_err(bus_error)?;
+        i2c.write(self.address, &[Self::REG_CTRL_MEAS, Self::CTRL_MEAS])
+            .map_err(bus_error)?;
+
+        // About 8 ms at x1 oversampling.
+        let mut status = [Self::STATUS_MEASURING];
+        for _ in 0..10 {
+            std::thread::sleep(Duration::from_millis(5));
+            i2c.write_read(self.address, &[Self::REG_STATUS], &mut status)
+                .map_err(bus_error)?;
+            if status[0] & Self::STATUS_MEASURING == 0 {
+                break;
+            }
+        }
+        if status[0] & Self::STATUS_MEASURING != 0 {
+            anyhow::bail!("Conversion did not finish");
+        }
+
+        let mut d = [0u8; 8];
+        i2c.write_read(self.address, &[Self::REG_DATA], &mut d)
+            .map_err(bus_error)?;
+        let adc_p = (d[0] as u32) << 12 | (d[1] as u32) << 4 | (d[2] as u32) >> 4;
+        let adc_t = (d[3] as u32) << 12 | (d[4] as u32) << 4 | (d[5] as u32) >> 4;
+        let adc_h = (d[6] as u32) << 8 | d[7] as u32;
+
+        let (temperature, pressure, humidity) = calibration.compensate(adc_t, adc_p, adc_h);
+        Ok(Reading::Climate {
+            temperature_c: temperature as f32,
+            humidity_pct: humidity as f32,
+            pressure_hpa: Some((pressure / 100.0) as f32),
+        })
+    }
+}
+
+// ST LIS3DH accelerometer, ±2 g in high resolution mode.
+pub struct Lis3dh {
+    address: u8,
+}
+
+impl Lis3dh {
+    const REG_WHO_AM_I: u8 = 0x0F;
+    const WHO_AM_I: u8 = 0x33;
+    const REG_CTRL1: u8 = 0x20;
+    const REG_CTRL4: u8 = 0x23;
+    const REG_OUT_X_L: u8 = 0x28;
+    // Register address auto-increment for multi-byte reads.
+    const AUTO_INCREMENT: u8 = 0x80;
+    // 400 Hz, normal power, X/Y/Z enabled.
+    const CTRL1: u8 = 0x77;
+    // Block data update, ±2 g, high resolution.
+    const CTRL4: u8 = 0x88;
+    // High resolution needs 7 samples to settle after power-up.
+    const TURN_ON: Duration = Duration::from_millis(20);
+    // 12-bit left justified, 1 mg per digit at ±2 g.
+    const G_PER_DIGIT: f32 = 0.001;
+
+    fn probe<I: I2c>(i2c: &mut I, address: u8) -> bool {
+        let mut id = [0u8];
+        i2c.write_read(address, &[Self::REG_WHO_AM_I], &mut id)
+            .is_ok()
+            && id[0] == Self::WHO_AM_I
+    }
+}
+
+impl<I: I2c> Sensor<I> for Lis3dh {
+    fn name(&self) -> &'static str {
+        "LIS3DH"
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn read(&mut self, i2c: &mut I) -> anyhow::Result<Reading> {
+        i2c.write(self.address, &[Self::REG_CTRL4, Self::CTRL4])
+            .map_err(bus_error)?;
+        i2c.write(self.address, &[Self::REG_CTRL1, Self::CTRL1])
+            .map_err(bus_error)?;
+        std::thread::sleep(Self::TURN_ON);
+
+        let mut out = [0u8; 6];
+        let register = Self::REG_OUT_X_L | Self::AUTO_INCREMENT;
+        i2c.write_read(self.address, &[register], &mut out)
+            .map_err(bus_error)?;
+        // Power down until the next read.
+        i2c.write(self.address, &[Self::REG_CTRL1, 0])
+            .map_err(bus_error)?;
+
+        let axis =
+            |i: usize| (i16::from_le_bytes([out[i], out[i + 1]]) >> 4) as f32 * Self::G_PER_DIGIT;
+        Ok(Reading::Motion {
+            x: axis(0),
+            y: axis(2),
+            z: axis(4),
+        })
+    }
+}
+
+// Everything found on the bus. Each address is claimed by the first part
+// that answers, the SHT3x and SHT4x share theirs.
+pub fn probe<I: I2c + 'static>(i2c: &mut I) -> Vec<Box<dyn Sensor<I>>> {
+    let mut sensors: Vec<Box<dyn Sensor<I>>> = Vec::new();
+
+    for address in [0x44, 0x45] {
+        if Sht3x::probe(i2c, address) {
+            sensors.push(Box::new(Sht3x { address }));
+        } else if Sht4x::probe(i2c, address) {
+            sensors.push(Box::new(Sht4x { address }));
+        }
+    }
+    for address in [0x76, 0x77] {
+        if Bme280::probe(i2c, address) {
+            sensors.push(Box::new(Bme280 {
+                address,
+                calibration: None,
+            }));
+        }
+    }
+    for address in [0x18, 0x19] {
+        if Lis3dh::probe(i2c, address) {
+            sensors.push(Box::new(Lis3dh { address }));
+        }
+    }
+
+    sensors
+}
+
+// Starts the sensor task: powers the expansion rail, probes the bus and then
+// reads every sensor each `SAMPLE_INTERVAL`, posting one `Event::Sensor` per
+// reading. The rail is only held while sampling.
+pub fn spawn<I>(mut i2c: I, rail: PowerRail, events: Sender) -> anyhow::Result<()>
+where
+    I: I2c + Send + 'static,
+{
+    std::thread::Builder::new()
+        .name("sensors".into())
+        .stack_size(4096)
+        .spawn(move || {
+            let mut sensors = match rail.acquire() {
+                Ok(_power) => probe(&mut i2c),
+                Err(e) => {
+                    warn!("Sensor rail failed: {:?}", e);
+                    return;
+                }
+            };
+            if sensors.is_empty() {
+                info!("No sensors on the expansion bus");
+                return;
+            }
+            for sensor in &sensors {
+                info!("Sensor {} at {:#04x}", sensor.name(), sensor.address());
+            }
+
+            loop {
+                match rail.acquire() {
+                    Ok(_power) => {
+                        for sensor in &mut sensors {
+                            match sensor.read(&mut i2c) {
+                                Ok(reading) => {
+                                    let sample = Sample {
+                                        sensor: sensor.name(),
+                                        address: sensor.address(),
+                                        reading,
+                                    };
+                                    if events.send(Event::Sensor(sample)).is_err() {
+                                        return;
+                                    }
+                                }
+                                Err(e) => warn!("{} read failed: {:?}", sensor.name(), e),
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Sensor rail failed: {:?}", e),
+                }
+                std::thread::sleep(SAMPLE_INTERVAL);
+            }
+        })?;
+    Ok(())
+}
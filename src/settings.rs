@@ -0,0 +1,159 @@
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::panel::PanelKind;
+
+// Shared by everything the firmware keeps in NVS.
+pub const NAMESPACE: &str = "tugger";
+const KEY: &str = "settings";
+
+// Panel orientation, named after how the unit is mounted.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    Rotate0,
+    #[default]
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Rotation {
+    pub fn is_portrait(self) -> bool {
+        matches!(self, Rotation::Rotate0 | Rotation::Rotate180)
+    }
+
+    pub fn degrees(self) -> u16 {
+        match self {
+            Rotation::Rotate0 => 0,
+            Rotation::Rotate90 => 90,
+            Rotation::Rotate180 => 180,
+            Rotation::Rotate270 => 270,
+        }
+    }
+
+    // Cycles clockwise, for stepping through orientations from the button.
+    pub fn next(self) -> Self {
+        match self {
+            Rotation::Rotate0 => Rotation::Rotate90,
+            Rotation::Rotate90 => Rotation::Rotate180,
+            Rotation::Rotate180 => Rotation::Rotate270,
+            Rotation::Rotate270 => Rotation::Rotate0,
+        }
+    }
+}
+
+// A node the composer can address.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Peer {
+    pub name: String,
+    pub address: u32,
+}
+
+// Device settings that survive a reboot. Missing fields fall back to their
+// defaults so older stored versions keep loading.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub rotation: Rotation,
+    // Which e-paper glass is fitted, `Auto` probes it at boot.
+    pub panel: PanelKind,
+    // Multiplied into battery readings to trim out divider tolerance.
+    pub battery_calibration: f32,
+    // Replies offered by the composer, in menu order.
+    pub canned_messages: Vec<String>,
+    // Destinations offered by the composer, in menu order.
+    pub peers: Vec<Peer>,
+    // Shared by the nodes of one network, empty until the unit is provisioned.
+    pub network_key: String,
+    // Page the provisioning QR code opens, the node's identity is appended.
+    pub provisioning_url: String,
+}
+
+impl Settings {
+    // Lets an installer compare keys by eye without the key itself being on
+    // the panel. FNV-1a, not meant to resist anyone.
+    pub fn key_fingerprint(&self) -> String {
+        if self.network_key.is_empty() {
+            return "none".to_string();
+        }
+        let hash = self.network_key.bytes().fold(0x811c_9dc5u32, |h, b| {
+            (h ^ b as u32).wrapping_mul(0x0100_0193)
+        });
+        format!("{:08x}", hash)
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let canned = [
+            "OK",
+            "On my way",
+            "Alongside",
+            "All fast",
+            "Standing by",
+            "Need assistance",
+        ];
+
+        Self {
+            rotation: Rotation::default(),
+            panel: PanelKind::default(),
+            battery_calibration: 1.0,
+            canned_messages: canned.iter().map(|m| m.to_string()).collect(),
+            peers: vec![Peer {
+                name: "Everyone".to_string(),
+                address: crate::radio::BROADCAST,
+            }],
+            network_key: String::new(),
+            provisioning_url: String::new(),
+        }
+    }
+}
+
+// Settings are kept as a single JSON string in NVS.
+pub struct SettingsStore {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl SettingsStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> anyhow::Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+        Ok(Self { nvs })
+    }
+
+    // Never fails: a missing or unreadable entry yields the defaults.
+    pub fn load(&self) -> Settings {
+        match self.read() {
+            Ok(Some(settings)) => settings,
+            Ok(None) => Settings::default(),
+            Err(e) => {
+                warn!("Stored settings unreadable, using defaults: {:?}", e);
+                Settings::default()
+            }
+        }
+    }
+
+    // Whether settings were ever saved, a unit fresh off the line has none.
+    pub fn stored(&self) -> anyhow::Result<bool> {
+        Ok(self.nvs.contains(KEY)?)
+    }
+
+    fn read(&self) -> anyhow::Result<Option<Settings>> {
+        let Some(len) = self.nvs.str_len(KEY)? else {
+            return Ok(None);
+        };
+
+        let mut buf = vec![0; len];
+        let Some(json) = self.nvs.get_str(KEY, &mut buf)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_str(json)?))
+    }
+
+    pub fn save(&mut self, settings: &Settings) -> anyhow::Result<()> {
+        let json = serde_json::to_string(settings)?;
+        self.nvs.set_str(KEY, &json)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+use esp_idf_hal::delay::Ets;
+use esp_idf_hal::gpio::*;
+use esp_idf_hal::spi::{config, SpiDeviceDriver, SpiDriver};
+use esp_idf_hal::units::Hertz;
+use esp_idf_svc::timer::EspAsyncTimer;
+use tugger_core::spi::{AsyncSharedSpiDevice, BusLock, DeviceConfig, SharedSpiDevice};
+
+// Delays shorter than this spin, longer ones sleep the task.
+const SPIN_LIMIT: Duration = Duration::from_millis(1);
+
+// `Operation::DelayNs` for the shared device: a busy-wait for short delays,
+// where a context switch would cost more than it saves, and a task sleep for
+// long ones so the rest of the system keeps running. Both wait at least the
+// requested time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpiDelay;
+
+impl DelayNs for SpiDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        let duration = Duration::from_nanos(ns as u64);
+        if duration < SPIN_LIMIT {
+            Ets::delay_us(ns.div_ceil(1000));
+        } else {
+            std::thread::sleep(duration);
+        }
+    }
+}
+
+// `SpiDelay` for async devices: the same spin for short delays, long ones
+// wait on an esp_timer so the executor runs other futures meanwhile.
+pub struct AsyncSpiDelay {
+    timer: EspAsyncTimer,
+}
+
+impl AsyncSpiDelay {
+    pub fn new(timer: EspAsyncTimer) -> Self {
+        Self { timer }
+    }
+}
+
+impl embedded_hal_async::delay::DelayNs for AsyncSpiDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        let duration = Duration::from_nanos(ns as u64);
+        if duration < SPIN_LIMIT {
+            Ets::delay_us(ns.div_ceil(1000));
+        } else if self.timer.after(duration).await.is_err() {
+            // Only fails if the timer can't be armed, the wait still has to
+            // happen.
+            std::thread::sleep(duration);
+        }
+    }
+}
+
+pub type EspSharedDevice<'a> = SharedSpiDevice<
+    'a,
+    SpiDeviceDriver<'static, &'a SpiDriver<'static>>,
+    PinDriver<'static, AnyOutputPin, Output>,
+    SpiDelay,
+>;
+
+pub type EspAsyncSharedDevice<'a> = AsyncSharedSpiDevice<
+    'a,
+    SpiDeviceDriver<'static, &'a SpiDriver<'static>>,
+    PinDriver<'static, AnyOutputPin, Output>,
+    AsyncSpiDelay,
+>;
+
+// The SPI host shared by the radio and the display. Every device gets its own
+// ESP-IDF device handle without hardware CS, which carries its mode and clock,
+// and a software CS through `SharedSpiDevice` or `AsyncSharedSpiDevice`.
+pub struct SharedBus {
+    driver: SpiDriver<'static>,
+    lock: BusLock,
+}
+
+impl SharedBus {
+    pub fn new(driver: SpiDriver<'static>) -> Self {
+        Self {
+            driver,
+            lock: BusLock::new(()),
+        }
+    }
+
+    pub fn device(
+        &self,
+        cs: PinDriver<'static, AnyOutputPin, Output>,
+        device: &DeviceConfig,
+    ) -> anyhow::Result<EspSharedDevice<'_>> {
+        let driver = self.driver(device)?;
+        Ok(SharedSpiDevice::new(&self.lock, driver, cs, SpiDelay)?)
+    }
+
+    // `timer` serves the device's long `DelayNs` operations.
+    pub fn async_device(
+        &self,
+        cs: PinDriver<'static, AnyOutputPin, Output>,
+        device: &DeviceConfig,
+        timer: EspAsyncTimer,
+    ) -> anyhow::Result<EspAsyncSharedDevice<'_>> {
+        let driver = self.driver(device)?;
+        Ok(AsyncSharedSpiDevice::new(
+            &self.lock,
+            driver,
+            cs,
+            AsyncSpiDelay::new(timer),
+        )?)
+    }
+
+    fn driver(
+        &self,
+        device: &DeviceConfig,
+    ) -> anyhow::Result<SpiDeviceDriver<'static, &SpiDriver<'static>>> {
+        let config = config::Config::new()
+            .baudrate(Hertz(device.frequency_hz))
+            .data_mode(device.mode);
+        Ok(SpiDeviceDriver::new(
+            &self.driver,
+            Option::<AnyOutputPin>::None,
+            &config,
+        )?)
+    }
+}
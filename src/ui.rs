@@ -0,0 +1,145 @@
+use embedded_graphics::{
+    prelude::*,
+    primitives::Rectangle,
+    text::{Baseline, Text},
+};
+use epd_waveshare::color::Color;
+
+use crate::font::{self, BitmapFont, FontStyle};
+
+// Shared layout for the full screen menus: a bold title line, a right aligned
+// status in the header and a body of single line rows.
+
+pub const MARGIN: i32 = 4;
+
+pub fn body_font() -> &'static BitmapFont {
+    &font::NOTO_SANS_16
+}
+
+// Everything below the header line.
+pub fn body_area(screen: Rectangle) -> Rectangle {
+    let header = font::NOTO_SANS_BOLD_20.line_height() as i32 + MARGIN;
+    Rectangle::new(
+        screen.top_left + Point::new(MARGIN, header),
+        Size::new(
+            screen.size.width.saturating_sub(2 * MARGIN as u32),
+            screen.size.height.saturating_sub(header as u32),
+        ),
+    )
+}
+
+pub fn lines_per_page(body: Rectangle) -> usize {
+    (body.size.height / body_font().line_height()).max(1) as usize
+}
+
+pub fn draw_header<D>(target: &mut D, title: &str, right: &str) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let screen = target.bounding_box();
+    let style = FontStyle::new(&font::NOTO_SANS_BOLD_20, Color::Black);
+    Text::with_baseline(
+        title,
+        screen.top_left + Point::new(MARGIN, 0),
+        style,
+        Baseline::Top,
+    )
+    .draw(target)?;
+
+    let style = FontStyle::new(body_font(), Color::Black);
+    let x = screen.top_left.x + screen.size.width as i32
+        - MARGIN
+        - body_font().text_width(right) as i32;
+    Text::with_baseline(
+        right,
+        Point::new(x, screen.top_left.y + MARGIN),
+        style,
+        Baseline::Top,
+    )
+    .draw(target)?;
+
+    Ok(())
+}
+
+// Draws `items` as rows with the `selected` one inverted, scrolling by whole
+// pages so the selection stays visible. `empty` is shown when there are no rows.
+pub fn draw_list<D>(
+    target: &mut D,
+    items: &[String],
+    selected: usize,
+    empty: &str,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let body = body_area(target.bounding_box());
+    let font = body_font();
+    if items.is_empty() {
+        let style = FontStyle::new(font, Color::Black);
+        Text::with_baseline(empty, body.top_left, style, Baseline::Top).draw(target)?;
+        return Ok(());
+    }
+
+    let page = lines_per_page(body);
+    let first = selected / page * page;
+    let row_height = font.line_height() as i32;
+
+    for (row, (i, item)) in items.iter().enumerate().skip(first).take(page).enumerate() {
+        let mut style = FontStyle::new(font, Color::Black);
+        if i == selected {
+            style.color = Color::White;
+            target.fill_solid(
+                &Rectangle::new(
+                    body.top_left + Point::new(-MARGIN, row as i32 * row_height),
+                    Size::new(body.size.width + 2 * MARGIN as u32, row_height as u32),
+                ),
+                Color::Black,
+            )?;
+        }
+
+        let line = truncate(font, item, body.size.width);
+        let origin = body.top_left + Point::new(0, row as i32 * row_height);
+        Text::with_baseline(&line, origin, style, Baseline::Top).draw(target)?;
+    }
+
+    Ok(())
+}
+
+// Word wrapped paragraphs in the body area, cut off after one page.
+pub fn draw_lines<D>(target: &mut D, lines: &[String]) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Color>,
+{
+    let body = body_area(target.bounding_box());
+    let font = body_font();
+    let style = FontStyle::new(font, Color::Black);
+
+    let wrapped = lines.iter().flat_map(|l| font.wrap(l, body.size.width));
+    for (row, line) in wrapped.take(lines_per_page(body)).enumerate() {
+        let origin = body.top_left + Point::new(0, row as i32 * font.line_height() as i32);
+        Text::with_baseline(&line, origin, style, Baseline::Top).draw(target)?;
+    }
+
+    Ok(())
+}
+
+// Cuts `text` to fit `width` pixels, ending in an ellipsis when shortened.
+pub fn truncate(font: &BitmapFont, text: &str, width: u32) -> String {
+    if font.text_width(text) <= width {
+        return text.to_string();
+    }
+
+    let budget = width.saturating_sub(font.text_width("…"));
+    let mut out = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let advance = font.glyph(c).advance as u32;
+        if used + advance > budget {
+            break;
+        }
+        used += advance;
+        out.push(c);
+    }
+    out.push('…');
+    out
+}
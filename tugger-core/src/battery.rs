@@ -0,0 +1,178 @@
+// Readings below this are a missing cell (USB only), not an empty one.
+const ABSENT_MV: u32 = 2500;
+// Warn below this.
+const LOW_MV: u32 = 3500;
+// Shut down below this, before the regulator browns out mid-write.
+const CRITICAL_MV: u32 = 3300;
+// A level is only left once the voltage has recovered by this much, so load
+// dips around a threshold don't flap between states.
+const HYSTERESIS_MV: u32 = 50;
+
+// Weight of a new reading in the moving average.
+const FILTER_ALPHA: f32 = 0.2;
+
+// Resting voltage of a typical Li-ion cell against state of charge, highest
+// first. Interpolated linearly in between.
+const DISCHARGE_CURVE: [(u32, u8); 12] = [
+    (4200, 100),
+    (4100, 90),
+    (4000, 80),
+    (3920, 70),
+    (3870, 60),
+    (3820, 50),
+    (3790, 40),
+    (3770, 30),
+    (3740, 20),
+    (3680, 10),
+    (3450, 5),
+    (3000, 0),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatteryLevel {
+    Absent,
+    Normal,
+    Low,
+    Critical,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatteryStatus {
+    pub millivolts: u32,
+    pub percent: u8,
+    pub level: BatteryLevel,
+}
+
+pub fn state_of_charge(millivolts: u32) -> u8 {
+    let (top, bottom) = (
+        DISCHARGE_CURVE[0],
+        DISCHARGE_CURVE[DISCHARGE_CURVE.len() - 1],
+    );
+    if millivolts >= top.0 {
+        return top.1;
+    }
+    if millivolts <= bottom.0 {
+        return bottom.1;
+    }
+
+    DISCHARGE_CURVE
+        .windows(2)
+        .find(|w| millivolts >= w[1].0)
+        .map(|w| {
+            let ((hi_mv, hi_pct), (lo_mv, lo_pct)) = (w[0], w[1]);
+            let t = (millivolts - lo_mv) as f32 / (hi_mv - lo_mv) as f32;
+            (lo_pct as f32 + t * (hi_pct - lo_pct) as f32).round() as u8
+        })
+        .unwrap_or(0)
+}
+
+// Filtering and thresholds, fed with the battery voltage in mV. Pure, the ADC
+// side lives in the firmware's `battery::BatteryAdc`.
+pub struct BatteryMonitor {
+    // Per-unit correction for divider tolerance, multiplied into every reading.
+    calibration: f32,
+    filtered: Option<f32>,
+    level: BatteryLevel,
+}
+
+impl BatteryMonitor {
+    pub fn new(calibration: f32) -> Self {
+        Self {
+            calibration,
+            filtered: None,
+            level: BatteryLevel::Normal,
+        }
+    }
+
+    pub fn update(&mut self, millivolts: u32) -> BatteryStatus {
+        let reading = millivolts as f32 * self.calibration;
+        let filtered = match self.filtered {
+            // Plugging a cell in or out jumps too far to average over.
+            Some(f) if (reading - f).abs() < ABSENT_MV as f32 / 2.0 => {
+                f + FILTER_ALPHA * (reading - f)
+            }
+            _ => reading,
+        };
+        self.filtered = Some(filtered);
+
+        let millivolts = filtered.round() as u32;
+        self.level = self.classify(millivolts);
+
+        BatteryStatus {
+            millivolts,
+            percent: state_of_charge(millivolts),
+            level: self.level,
+        }
+    }
+
+    fn classify(&self, millivolts: u32) -> BatteryLevel {
+        if millivolts < ABSENT_MV {
+            return BatteryLevel::Absent;
+        }
+
+        // Getting out of a level takes the hysteresis margin on top.
+        let critical_below = match self.level {
+            BatteryLevel::Critical => CRITICAL_MV + HYSTERESIS_MV,
+            _ => CRITICAL_MV,
+        };
+        let low_below = match self.level {
+            BatteryLevel::Low | BatteryLevel::Critical => LOW_MV + HYSTERESIS_MV,
+            _ => LOW_MV,
+        };
+
+        if millivolts < critical_below {
+            BatteryLevel::Critical
+        } else if millivolts < low_below {
+            BatteryLevel::Low
+        } else {
+            BatteryLevel::Normal
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds the same reading until the filter has caught up with it.
+    fn settle(monitor: &mut BatteryMonitor, millivolts: u32) -> BatteryStatus {
+        (0..50).map(|_| monitor.update(millivolts)).last().unwrap()
+    }
+
+    #[test]
+    fn charge_follows_the_discharge_curve() {
+        assert_eq!(state_of_charge(4350), 100);
+        assert_eq!(state_of_charge(4200), 100);
+        assert_eq!(state_of_charge(3845), 55);
+        assert_eq!(state_of_charge(3680), 10);
+        assert_eq!(state_of_charge(2900), 0);
+    }
+
+    #[test]
+    fn levels_need_the_hysteresis_to_recover() {
+        let mut monitor = BatteryMonitor::new(1.0);
+        assert_eq!(settle(&mut monitor, 3600).level, BatteryLevel::Normal);
+        assert_eq!(settle(&mut monitor, 3490).level, BatteryLevel::Low);
+        assert_eq!(settle(&mut monitor, 3520).level, BatteryLevel::Low);
+        assert_eq!(settle(&mut monitor, 3290).level, BatteryLevel::Critical);
+        assert_eq!(settle(&mut monitor, 3330).level, BatteryLevel::Critical);
+        assert_eq!(settle(&mut monitor, 3360).level, BatteryLevel::Low);
+        assert_eq!(settle(&mut monitor, 3560).level, BatteryLevel::Normal);
+    }
+
+    #[test]
+    fn pulling_the_cell_skips_the_filter() {
+        let mut monitor = BatteryMonitor::new(1.0);
+        settle(&mut monitor, 3900);
+
+        let status = monitor.update(40);
+        assert_eq!(status.level, BatteryLevel::Absent);
+        assert_eq!(status.millivolts, 40);
+    }
+
+    #[test]
+    fn calibration_scales_readings() {
+        let mut monitor = BatteryMonitor::new(1.05);
+        assert_eq!(monitor.update(4000).millivolts, 4200);
+    }
+}
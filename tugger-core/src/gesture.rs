@@ -0,0 +1,260 @@
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    Short,
+    // Fired once the hold passes `Timings::long_press`, while still held.
+    Long,
+    Double,
+    // Fired every `Timings::repeat` after `Long` for as long as the button is held.
+    Repeat,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Timings {
+    // The level has to be stable this long before it counts.
+    pub debounce: Duration,
+    pub long_press: Duration,
+    // Max gap between the first release and the second press of a double press.
+    pub double_gap: Duration,
+    pub repeat: Duration,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(30),
+            long_press: Duration::from_millis(700),
+            double_gap: Duration::from_millis(300),
+            repeat: Duration::from_millis(250),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Idle,
+    Pressed { since: Duration },
+    // Released after a short press, waiting to see if a second press follows.
+    Released { at: Duration },
+    // Past the long press threshold, repeating until release.
+    Held { next_repeat: Duration },
+    // Second press of a double, swallowed until release.
+    SecondPress,
+}
+
+// Debounce and gesture state machine for one button. It knows nothing about
+// pins or clocks: feed it the raw level with `input` and ask for gestures
+// with `poll`, both with a monotonic timestamp, so it can be driven from
+// recorded or synthetic edge timings as well as from the hardware.
+pub struct GestureDetector {
+    timings: Timings,
+    raw: bool,
+    raw_since: Duration,
+    pressed: bool,
+    state: State,
+}
+
+impl GestureDetector {
+    pub fn new(timings: Timings) -> Self {
+        Self {
+            timings,
+            raw: false,
+            raw_since: Duration::ZERO,
+            pressed: false,
+            state: State::Idle,
+        }
+    }
+
+    // Raw (bouncy) level at `now`. Repeating the current level is a no-op.
+    pub fn input(&mut self, pressed: bool, now: Duration) {
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.raw_since = now;
+        }
+    }
+
+    // True while a gesture may still produce events without further edges,
+    // the caller should keep polling until this goes false.
+    pub fn is_active(&self) -> bool {
+        self.state != State::Idle || self.raw != self.pressed
+    }
+
+    // Advances to `now` and returns at most one gesture. Call again until it
+    // returns `None` in case several became due at once.
+    pub fn poll(&mut self, now: Duration) -> Option<Gesture> {
+        // A level only counts once it has been stable for the debounce time,
+        // and the change is dated to when that happened.
+        if self.raw != self.pressed && now >= self.raw_since + self.timings.debounce {
+            self.pressed = self.raw;
+            let at = self.raw_since + self.timings.debounce;
+            if let Some(gesture) = self.transition(at) {
+                return Some(gesture);
+            }
+        }
+
+        self.timeout(now)
+    }
+
+    fn transition(&mut self, at: Duration) -> Option<Gesture> {
+        let (state, gesture) = match (self.state, self.pressed) {
+            (State::Idle, true) => (State::Pressed { since: at }, None),
+            // Released past the threshold without a poll in between.
+            (State::Pressed { since }, false) if at >= since + self.timings.long_press => {
+                (State::Idle, Some(Gesture::Long))
+            }
+            (State::Pressed { .. }, false) => (State::Released { at }, None),
+            (State::Released { at: released }, true) if at < released + self.timings.double_gap => {
+                (State::SecondPress, Some(Gesture::Double))
+            }
+            // The gap ran out before `poll` noticed: the first press was a
+            // short one and this press starts over.
+            (State::Released { .. }, true) => (State::Pressed { since: at }, Some(Gesture::Short)),
+            (State::Held { .. } | State::SecondPress, false) => (State::Idle, None),
+            (state, _) => (state, None),
+        };
+        self.state = state;
+        gesture
+    }
+
+    fn timeout(&mut self, now: Duration) -> Option<Gesture> {
+        match self.state {
+            State::Pressed { since } if now >= since + self.timings.long_press => {
+                self.state = State::Held {
+                    next_repeat: since + self.timings.long_press + self.timings.repeat,
+                };
+                Some(Gesture::Long)
+            }
+            State::Released { at } if now >= at + self.timings.double_gap => {
+                self.state = State::Idle;
+                Some(Gesture::Short)
+            }
+            State::Held { next_repeat } if now >= next_repeat => {
+                self.state = State::Held {
+                    next_repeat: next_repeat + self.timings.repeat,
+                };
+                Some(Gesture::Repeat)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    // Feeds raw `edges` (ms, pressed) and polls every ms up to `until`.
+    // Returns the gestures with the time they fired.
+    fn run(edges: &[(u64, bool)], until: u64) -> Vec<(u64, Gesture)> {
+        let mut detector = GestureDetector::new(Timings::default());
+        let mut fired = Vec::new();
+        for now in 0..=until {
+            for &(_, pressed) in edges.iter().filter(|(at, _)| *at == now) {
+                detector.input(pressed, ms(now));
+            }
+            while let Some(gesture) = detector.poll(ms(now)) {
+                fired.push((now, gesture));
+            }
+        }
+        fired
+    }
+
+    fn gestures(fired: &[(u64, Gesture)]) -> Vec<Gesture> {
+        fired.iter().map(|&(_, g)| g).collect()
+    }
+
+    #[test]
+    fn bounce_shorter_than_debounce_is_ignored() {
+        let edges = [
+            (0, true),
+            (3, false),
+            (7, true),
+            (12, false),
+            (20, true),
+            (25, false),
+        ];
+        assert_eq!(run(&edges, 2000), []);
+    }
+
+    #[test]
+    fn bouncy_press_counts_once() {
+        let edges = [
+            (0, true),
+            (2, false),
+            (4, true),
+            (150, false),
+            (152, true),
+            (154, false),
+        ];
+        assert_eq!(gestures(&run(&edges, 2000)), [Gesture::Short]);
+    }
+
+    #[test]
+    fn short_press_fires_after_double_gap() {
+        let fired = run(&[(0, true), (100, false)], 2000);
+        // Release is debounced at 130, the double press window ends 300 later.
+        assert_eq!(fired, [(430, Gesture::Short)]);
+    }
+
+    #[test]
+    fn long_press_fires_while_held() {
+        let fired = run(&[(0, true), (800, false)], 2000);
+        // Press is debounced at 30, long after 700 more.
+        assert_eq!(fired, [(730, Gesture::Long)]);
+    }
+
+    #[test]
+    fn long_press_released_between_polls() {
+        let mut detector = GestureDetector::new(Timings::default());
+        detector.input(true, ms(0));
+        assert_eq!(detector.poll(ms(30)), None);
+        detector.input(false, ms(900));
+        assert_eq!(detector.poll(ms(2000)), Some(Gesture::Long));
+        assert_eq!(detector.poll(ms(2000)), None);
+        assert!(!detector.is_active());
+    }
+
+    #[test]
+    fn double_press() {
+        let edges = [(0, true), (100, false), (250, true), (350, false)];
+        assert_eq!(run(&edges, 2000), [(280, Gesture::Double)]);
+    }
+
+    #[test]
+    fn presses_further_apart_than_double_gap_are_two_shorts() {
+        let edges = [(0, true), (100, false), (500, true), (600, false)];
+        assert_eq!(
+            gestures(&run(&edges, 2000)),
+            [Gesture::Short, Gesture::Short]
+        );
+    }
+
+    #[test]
+    fn hold_repeats_after_long() {
+        let fired = run(&[(0, true), (1300, false)], 2000);
+        assert_eq!(
+            fired,
+            [
+                (730, Gesture::Long),
+                (980, Gesture::Repeat),
+                (1230, Gesture::Repeat),
+            ]
+        );
+    }
+
+    #[test]
+    fn idle_after_gesture() {
+        let mut detector = GestureDetector::new(Timings::default());
+        assert!(!detector.is_active());
+        detector.input(true, ms(0));
+        assert!(detector.is_active());
+        detector.input(false, ms(100));
+        while detector.poll(ms(1000)).is_some() {}
+        assert!(!detector.is_active());
+    }
+}
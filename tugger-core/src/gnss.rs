@@ -0,0 +1,661 @@
+use std::time::Duration;
+
+use log::*;
+
+// NMEA caps sentences at 82 characters, anything longer is line noise.
+const MAX_SENTENCE: usize = 100;
+// Largest UBX payload we care about, NAV-PVT is 92 bytes.
+const MAX_UBX_PAYLOAD: usize = 128;
+
+const KNOTS_TO_KMH: f32 = 1.852;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UtcTime {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub millis: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+// Navigation solution for one epoch, merged from the sentences that carry
+// its parts.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Fix {
+    // The receiver considers the position usable.
+    pub valid: bool,
+    pub time: Option<UtcTime>,
+    pub date: Option<Date>,
+    // Degrees, north and east positive.
+    pub latitude: f64,
+    pub longitude: f64,
+    // Above mean sea level.
+    pub altitude_m: Option<f32>,
+    pub speed_kmh: f32,
+    // Course over ground, true north. Receivers leave it empty at rest.
+    pub course_deg: Option<f32>,
+    pub satellites: u8,
+    // NMEA reports HDOP, NAV-PVT only PDOP.
+    pub hdop: Option<f32>,
+    pub pdop: Option<f32>,
+}
+
+impl Fix {
+    // Seconds since the Unix epoch, once both date and time are known.
+    pub fn unix_time(&self) -> Option<Duration> {
+        let (date, time) = (self.date?, self.time?);
+        let days = days_from_civil(date.year as i64, date.month as i64, date.day as i64);
+        let seconds =
+            days * 86400 + time.hour as i64 * 3600 + time.minute as i64 * 60 + time.second as i64;
+        let seconds = u64::try_from(seconds).ok()?;
+        Some(Duration::from_secs(seconds) + Duration::from_millis(time.millis as u64))
+    }
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's
+// algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NmeaError {
+    // No `$`, no `*hh` or not ASCII.
+    Framing,
+    Checksum,
+    // A field the sentence needs is missing or malformed.
+    Field(usize),
+}
+
+// Recommended minimum data: time, date, validity, position, speed, course.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rmc {
+    pub time: Option<UtcTime>,
+    pub date: Option<Date>,
+    pub valid: bool,
+    pub position: Option<(f64, f64)>,
+    pub speed_kmh: Option<f32>,
+    pub course_deg: Option<f32>,
+}
+
+// Fix data: time, quality, satellites in use, HDOP, altitude.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Gga {
+    pub time: Option<UtcTime>,
+    // 0 = no fix, 1 = GPS, 2 = DGPS, 4/5 = RTK, 6 = dead reckoning.
+    pub quality: u8,
+    pub satellites: u8,
+    pub hdop: Option<f32>,
+    pub altitude_m: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sentence {
+    Rmc(Rmc),
+    Gga(Gga),
+    // Valid, but not one we use (GSA, GSV, VTG, ...).
+    Other,
+}
+
+// Parses one sentence, `$` to checksum, with or without the line ending.
+// Any talker is accepted (GP, GN, GL, GA, BD).
+pub fn parse_sentence(line: &str) -> Result<Sentence, NmeaError> {
+    let line = line.trim_end();
+    let body = line.strip_prefix('$').ok_or(NmeaError::Framing)?;
+    let (body, checksum) = body.split_once('*').ok_or(NmeaError::Framing)?;
+    let expected = u8::from_str_radix(checksum, 16).map_err(|_| NmeaError::Framing)?;
+    if !body.is_ascii() {
+        return Err(NmeaError::Framing);
+    }
+    if body.bytes().fold(0, |sum, b| sum ^ b) != expected {
+        return Err(NmeaError::Checksum);
+    }
+
+    let fields: Vec<&str> = body.split(',').collect();
+    let kind = fields[0].get(2..).ok_or(NmeaError::Field(0))?;
+    match kind {
+        "RMC" => parse_rmc(&fields).map(Sentence::Rmc),
+        "GGA" => parse_gga(&fields).map(Sentence::Gga),
+        _ => Ok(Sentence::Other),
+    }
+}
+
+fn field<'a>(fields: &[&'a str], i: usize) -> &'a str {
+    fields.get(i).copied().unwrap_or("")
+}
+
+// Empty fields are `None`, malformed ones an error.
+fn number<T: std::str::FromStr>(fields: &[&str], i: usize) -> Result<Option<T>, NmeaError> {
+    match field(fields, i) {
+        "" => Ok(None),
+        text => text.parse().map(Some).map_err(|_| NmeaError::Field(i)),
+    }
+}
+
+// hhmmss(.sss)
+fn parse_time(fields: &[&str], i: usize) -> Result<Option<UtcTime>, NmeaError> {
+    let text = field(fields, i);
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let err = NmeaError::Field(i);
+    let (hms, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if hms.len() != 6 {
+        return Err(err);
+    }
+    let part = |range: std::ops::Range<usize>| hms[range].parse::<u8>().map_err(|_| err);
+    let millis = match fraction {
+        "" => 0,
+        f => {
+            let digits: String = f.chars().chain("000".chars()).take(3).collect();
+            digits.parse().map_err(|_| err)?
+        }
+    };
+    Ok(Some(UtcTime {
+        hour: part(0..2)?,
+        minute: part(2..4)?,
+        second: part(4..6)?,
+        millis,
+    }))
+}
+
+// ddmmyy, years taken as 20yy.
+fn parse_date(fields: &[&str], i: usize) -> Result<Option<Date>, NmeaError> {
+    let text = field(fields, i);
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let err = NmeaError::Field(i);
+    if text.len() != 6 {
+        return Err(err);
+    }
+    let part = |range: std::ops::Range<usize>| text[range].parse::<u8>().map_err(|_| err);
+    Ok(Some(Date {
+        day: part(0..2)?,
+        month: part(2..4)?,
+        year: 2000 + part(4..6)? as u16,
+    }))
+}
+
+// (d)ddmm.mmmm plus hemisphere in the next field.
+fn parse_coordinate(fields: &[&str], i: usize) -> Result<Option<f64>, NmeaError> {
+    let text = field(fields, i);
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let err = NmeaError::Field(i);
+    let dot = text.find('.').unwrap_or(text.len());
+    if dot < 3 {
+        return Err(err);
+    }
+    let degrees: f64 = text[..dot - 2].parse().map_err(|_| err)?;
+    let minutes: f64 = text[dot - 2..].parse().map_err(|_| err)?;
+    let value = degrees + minutes / 60.0;
+    match field(fields, i + 1) {
+        "N" | "E" => Ok(Some(value)),
+        "S" | "W" => Ok(Some(-value)),
+        _ => Err(NmeaError::Field(i + 1)),
+    }
+}
+
+fn parse_position(fields: &[&str], i: usize) -> Result<Option<(f64, f64)>, NmeaError> {
+    let latitude = parse_coordinate(fields, i)?;
+    let longitude = parse_coordinate(fields, i + 2)?;
+    Ok(latitude.zip(longitude))
+}
+
+fn parse_rmc(fields: &[&str]) -> Result<Rmc, NmeaError> {
+    Ok(Rmc {
+        time: parse_time(fields, 1)?,
+        valid: field(fields, 2) == "A",
+        position: parse_position(fields, 3)?,
+        speed_kmh: number::<f32>(fields, 7)?.map(|knots| knots * KNOTS_TO_KMH),
+        course_deg: number(fields, 8)?,
+        date: parse_date(fields, 9)?,
+    })
+}
+
+fn parse_gga(fields: &[&str]) -> Result<Gga, NmeaError> {
+    Ok(Gga {
+        time: parse_time(fields, 1)?,
+        quality: number(fields, 6)?.unwrap_or(0),
+        satellites: number(fields, 7)?.unwrap_or(0),
+        hdop: number(fields, 8)?,
+        altitude_m: number(fields, 9)?,
+    })
+}
+
+// u-blox NAV-PVT, the complete solution in one binary message.
+fn parse_nav_pvt(p: &[u8]) -> Option<Fix> {
+    if p.len() < 92 {
+        return None;
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([p[i], p[i + 1]]);
+    let i32_at = |i: usize| i32::from_le_bytes([p[i], p[i + 1], p[i + 2], p[i + 3]]);
+
+    // valid: bit 0 date, bit 1 time. flags: bit 0 gnssFixOK.
+    let valid_date = p[11] & 0x01 != 0;
+    let valid_time = p[11] & 0x02 != 0;
+    let fix_ok = p[21] & 0x01 != 0;
+    let fix_type = p[20];
+    let nano = i32_at(16);
+
+    let speed_kmh = i32_at(60) as f32 / 1000.0 * 3.6;
+    Some(Fix {
+        valid: fix_ok && (2..=4).contains(&fix_type),
+        time: valid_time.then(|| UtcTime {
+            hour: p[8],
+            minute: p[9],
+            second: p[10],
+            millis: (nano.max(0) / 1_000_000) as u16,
+        }),
+        date: valid_date.then(|| Date {
+            year: u16_at(4),
+            month: p[6],
+            day: p[7],
+        }),
+        latitude: i32_at(28) as f64 * 1e-7,
+        longitude: i32_at(24) as f64 * 1e-7,
+        altitude_m: (fix_type >= 3).then(|| i32_at(36) as f32 / 1000.0),
+        speed_kmh,
+        course_deg: (speed_kmh > 0.5).then(|| i32_at(64) as f32 * 1e-5),
+        satellites: p[23],
+        hdop: None,
+        pdop: Some(u16_at(76) as f32 * 0.01),
+    })
+}
+
+enum State {
+    Idle,
+    Nmea(Vec<u8>),
+    // Header bytes after the two sync chars, then the payload and checksum.
+    Ubx(Vec<u8>),
+}
+
+// The sentences of one NMEA epoch, which all carry the same time tag.
+#[derive(Default)]
+struct Epoch {
+    time: Option<UtcTime>,
+    rmc: Option<Rmc>,
+    gga: Option<Gga>,
+}
+
+impl Epoch {
+    fn started(&self) -> bool {
+        self.rmc.is_some() || self.gga.is_some()
+    }
+
+    // Validity and position come from RMC, an epoch without one is dropped.
+    fn fix(&self) -> Option<Fix> {
+        let rmc = self.rmc?;
+        let (latitude, longitude) = rmc.position.unwrap_or_default();
+        let mut fix = Fix {
+            valid: rmc.valid,
+            time: rmc.time,
+            date: rmc.date,
+            latitude,
+            longitude,
+            speed_kmh: rmc.speed_kmh.unwrap_or(0.0),
+            course_deg: rmc.course_deg,
+            ..Fix::default()
+        };
+        if let Some(gga) = self.gga {
+            fix.satellites = gga.satellites;
+            fix.hdop = gga.hdop;
+            fix.altitude_m = if gga.quality > 0 {
+                gga.altitude_m
+            } else {
+                None
+            };
+        }
+        Some(fix)
+    }
+}
+
+// Splits the receiver's byte stream into NMEA sentences and UBX frames and
+// merges them into fixes. Receivers differ in the order of an epoch's
+// sentences (u-blox sends RMC, VTG, then GGA), so an epoch is complete once
+// it has both RMC and GGA, or ends when the time tag changes or a sentence
+// kind comes round again.
+pub struct Decoder {
+    state: State,
+    ubx_sync: bool,
+    epoch: Epoch,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            state: State::Idle,
+            ubx_sync: false,
+            epoch: Epoch::default(),
+        }
+    }
+}
+
+impl Decoder {
+    // Feeds one byte, returns a fix when an epoch is complete.
+    pub fn push(&mut self, byte: u8) -> Option<Fix> {
+        match &mut self.state {
+            State::Idle => {
+                match byte {
+                    b'$' => self.state = State::Nmea(vec![byte]),
+                    0x62 if self.ubx_sync => self.state = State::Ubx(Vec::new()),
+                    _ => {}
+                }
+                self.ubx_sync = byte == 0xB5;
+                None
+            }
+            State::Nmea(line) => {
+                if byte == b'\n' || byte == b'\r' {
+                    let line = std::mem::take(line);
+                    self.state = State::Idle;
+                    return self.sentence(&line);
+                }
+                line.push(byte);
+                if line.len() > MAX_SENTENCE {
+                    self.state = State::Idle;
+                }
+                None
+            }
+            State::Ubx(frame) => {
+                frame.push(byte);
+                // class, id, length (LE), payload, ck_a, ck_b
+                if frame.len() < 4 {
+                    return None;
+                }
+                let len = u16::from_le_bytes([frame[2], frame[3]]) as usize;
+                if len > MAX_UBX_PAYLOAD {
+                    self.state = State::Idle;
+                    return None;
+                }
+                if frame.len() < 4 + len + 2 {
+                    return None;
+                }
+                let frame = std::mem::take(frame);
+                self.state = State::Idle;
+                self.ubx(&frame)
+            }
+        }
+    }
+
+    fn sentence(&mut self, line: &[u8]) -> Option<Fix> {
+        let line = std::str::from_utf8(line).ok()?;
+        let sentence = match parse_sentence(line) {
+            Ok(Sentence::Other) => return None,
+            Ok(sentence) => sentence,
+            Err(e) => {
+                debug!("Dropped NMEA sentence ({:?}): {}", e, line);
+                return None;
+            }
+        };
+        let (time, repeat) = match &sentence {
+            Sentence::Rmc(rmc) => (rmc.time, self.epoch.rmc.is_some()),
+            Sentence::Gga(gga) => (gga.time, self.epoch.gga.is_some()),
+            Sentence::Other => return None,
+        };
+
+        let closed = if self.epoch.started() && (time != self.epoch.time || repeat) {
+            std::mem::take(&mut self.epoch).fix()
+        } else {
+            None
+        };
+        self.epoch.time = time;
+        match sentence {
+            Sentence::Rmc(rmc) => self.epoch.rmc = Some(rmc),
+            Sentence::Gga(gga) => self.epoch.gga = Some(gga),
+            Sentence::Other => {}
+        }
+        // Both parts are in, no need to wait for the next epoch.
+        if self.epoch.rmc.is_some() && self.epoch.gga.is_some() {
+            return std::mem::take(&mut self.epoch).fix();
+        }
+        closed
+    }
+
+    fn ubx(&mut self, frame: &[u8]) -> Option<Fix> {
+        let (body, checksum) = frame.split_at(frame.len() - 2);
+        let (mut a, mut b) = (0u8, 0u8);
+        for byte in body {
+            a = a.wrapping_add(*byte);
+            b = b.wrapping_add(a);
+        }
+        if checksum != [a, b] {
+            debug!("Dropped UBX frame, bad checksum");
+            return None;
+        }
+
+        match (body[0], body[1]) {
+            // NAV-PVT
+            (0x01, 0x07) => parse_nav_pvt(&body[4..]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // u-blox M10 output: RMC, VTG, GGA, GSA, GSV, GLL per epoch. The first
+    // epoch has no fix yet, the third is on the move.
+    const UBLOX: &str = include_str!("../tests/fixtures/gnss-ublox.nmea");
+    // MediaTek order, GGA ahead of RMC. The log stops after a GGA.
+    const MEDIATEK: &str = include_str!("../tests/fixtures/gnss-mediatek.nmea");
+
+    fn decode(bytes: &[u8]) -> Vec<Fix> {
+        let mut decoder = Decoder::default();
+        bytes.iter().filter_map(|b| decoder.push(*b)).collect()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    fn time(hour: u8, minute: u8, second: u8, millis: u16) -> Option<UtcTime> {
+        Some(UtcTime {
+            hour,
+            minute,
+            second,
+            millis,
+        })
+    }
+
+    // NAV-PVT payload for 2024-03-17 10:15:01.250, 3D fix.
+    fn nav_pvt() -> Vec<u8> {
+        let mut p = vec![0u8; 92];
+        p[4..6].copy_from_slice(&2024u16.to_le_bytes());
+        p[6..11].copy_from_slice(&[3, 17, 10, 15, 1]);
+        p[11] = 0x03;
+        p[16..20].copy_from_slice(&250_000_000i32.to_le_bytes());
+        p[20] = 3;
+        p[21] = 0x01;
+        p[23] = 11;
+        p[24..28].copy_from_slice(&115_166_707i32.to_le_bytes());
+        p[28..32].copy_from_slice(&481_173_020i32.to_le_bytes());
+        p[36..40].copy_from_slice(&519_400i32.to_le_bytes());
+        p[60..64].copy_from_slice(&5_000i32.to_le_bytes());
+        p[64..68].copy_from_slice(&9_000_000i32.to_le_bytes());
+        p[76..78].copy_from_slice(&185u16.to_le_bytes());
+        p
+    }
+
+    fn ubx_frame(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut body = vec![class, id];
+        body.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        body.extend_from_slice(payload);
+        let (mut a, mut b) = (0u8, 0u8);
+        for byte in &body {
+            a = a.wrapping_add(*byte);
+            b = b.wrapping_add(a);
+        }
+        let mut frame = vec![0xB5, 0x62];
+        frame.extend(body);
+        frame.extend([a, b]);
+        frame
+    }
+
+    #[test]
+    fn days_from_civil_matches_the_calendar() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19782);
+        assert_eq!(days_from_civil(2024, 3, 17), 19799);
+    }
+
+    #[test]
+    fn parses_rmc() {
+        let Ok(Sentence::Rmc(rmc)) = parse_sentence(
+            "$GNRMC,101502.00,A,4807.03950,N,01131.00410,E,10.800,54.70,170324,,,A*78\r\n",
+        ) else {
+            panic!("not an RMC");
+        };
+        assert!(rmc.valid);
+        assert_eq!(rmc.time, time(10, 15, 2, 0));
+        assert_eq!(
+            rmc.date,
+            Some(Date {
+                year: 2024,
+                month: 3,
+                day: 17
+            })
+        );
+        let (latitude, longitude) = rmc.position.unwrap();
+        assert!(close(latitude, 48.1173250));
+        assert!(close(longitude, 11.5167350));
+        assert!((rmc.speed_kmh.unwrap() - 20.0016).abs() < 1e-3);
+        assert_eq!(rmc.course_deg, Some(54.7));
+    }
+
+    #[test]
+    fn parses_gga() {
+        let Ok(Sentence::Gga(gga)) = parse_sentence(
+            "$GPGGA,064951.000,2307.1256,S,12016.4438,W,1,8,0.95,39.9,M,17.8,M,,*6C",
+        ) else {
+            panic!("not a GGA");
+        };
+        assert_eq!(gga.time, time(6, 49, 51, 0));
+        assert_eq!(gga.quality, 1);
+        assert_eq!(gga.satellites, 8);
+        assert_eq!(gga.hdop, Some(0.95));
+        assert_eq!(gga.altitude_m, Some(39.9));
+    }
+
+    #[test]
+    fn rejects_bad_sentences() {
+        assert_eq!(
+            parse_sentence("GPGGA,064951.000*00"),
+            Err(NmeaError::Framing)
+        );
+        assert_eq!(parse_sentence("$GPGGA,064951.000"), Err(NmeaError::Framing));
+        assert_eq!(
+            parse_sentence("$GNVTG,,,,,,,,,N*2F"),
+            Err(NmeaError::Checksum)
+        );
+        assert_eq!(parse_sentence("$GNVTG,,,,,,,,,N*2E"), Ok(Sentence::Other));
+        assert_eq!(
+            parse_sentence("$GNGGA,1015,,,,,0,,,,,,,,*7D"),
+            Err(NmeaError::Field(1))
+        );
+        assert_eq!(
+            parse_sentence("$GNRMC,,A,4807.0,X,01131.0,E,,,,,,A*71"),
+            Err(NmeaError::Field(4))
+        );
+    }
+
+    #[test]
+    fn parses_nav_pvt() {
+        let fix = parse_nav_pvt(&nav_pvt()).unwrap();
+        assert!(fix.valid);
+        assert_eq!(fix.time, time(10, 15, 1, 250));
+        assert_eq!(
+            fix.unix_time(),
+            Some(Duration::from_millis(1_710_670_501_250))
+        );
+        assert!(close(fix.latitude, 48.1173020));
+        assert!(close(fix.longitude, 11.5166707));
+        assert_eq!(fix.altitude_m, Some(519.4));
+        assert!((fix.speed_kmh - 18.0).abs() < 1e-3);
+        assert_eq!(fix.course_deg, Some(90.0));
+        assert_eq!(fix.satellites, 11);
+        assert_eq!(fix.hdop, None);
+        assert!((fix.pdop.unwrap() - 1.85).abs() < 1e-3);
+
+        assert_eq!(parse_nav_pvt(&nav_pvt()[..91]), None);
+    }
+
+    #[test]
+    fn ublox_epochs_close_on_gga() {
+        let fixes = decode(UBLOX.as_bytes());
+        assert_eq!(fixes.len(), 3);
+
+        assert!(!fixes[0].valid);
+        assert_eq!(fixes[0].satellites, 2);
+        assert_eq!(fixes[0].altitude_m, None);
+
+        assert!(fixes[1].valid);
+        assert_eq!(fixes[1].time, time(10, 15, 1, 0));
+        assert_eq!(fixes[1].satellites, 7);
+        assert_eq!(fixes[1].hdop, Some(1.32));
+        assert_eq!(fixes[1].altitude_m, Some(519.4));
+        assert_eq!(fixes[1].course_deg, None);
+        assert_eq!(
+            fixes[1].unix_time(),
+            Some(Duration::from_secs(1_710_670_501))
+        );
+
+        assert_eq!(fixes[2].time, time(10, 15, 2, 0));
+        assert_eq!(fixes[2].satellites, 9);
+        assert_eq!(fixes[2].altitude_m, Some(521.0));
+        assert_eq!(fixes[2].course_deg, Some(54.7));
+    }
+
+    #[test]
+    fn mediatek_epochs_close_on_rmc() {
+        let fixes = decode(MEDIATEK.as_bytes());
+        assert_eq!(fixes.len(), 2);
+        assert_eq!(fixes[0].time, time(6, 49, 51, 0));
+        assert_eq!(fixes[0].satellites, 8);
+        assert_eq!(fixes[0].altitude_m, Some(39.9));
+        assert_eq!(fixes[1].time, time(6, 49, 52, 0));
+        assert_eq!(fixes[1].satellites, 9);
+        assert_eq!(fixes[1].altitude_m, Some(40.1));
+    }
+
+    #[test]
+    fn rmc_only_epochs_close_on_the_next_time_tag() {
+        let log = "$GNRMC,101501.00,A,4807.03812,N,01131.00024,E,0.214,,170324,,,A*69\r\n\
+                   $GNRMC,101502.00,A,4807.03950,N,01131.00410,E,10.800,54.70,170324,,,A*78\r\n";
+        let fixes = decode(log.as_bytes());
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(fixes[0].time, time(10, 15, 1, 0));
+        assert_eq!(fixes[0].satellites, 0);
+    }
+
+    #[test]
+    fn decodes_ubx_between_sentences() {
+        let mut stream = b"$GNVTG,,,,,,,,,N*2E\r\n".to_vec();
+        stream.extend(ubx_frame(0x01, 0x07, &nav_pvt()));
+        stream.extend(ubx_frame(0x01, 0x35, &[0; 8]));
+        let mut corrupt = ubx_frame(0x01, 0x07, &nav_pvt());
+        corrupt[10] ^= 0xff;
+        stream.extend(corrupt);
+        stream.extend(b"$GNVTG,,,,,,,,,N*2E\r\n");
+
+        let fixes = decode(&stream);
+        assert_eq!(fixes.len(), 1);
+        assert!(fixes[0].pdop.is_some());
+    }
+}
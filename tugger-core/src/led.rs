@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+// Timer period, every step lasts a multiple of it.
+pub const TICK: Duration = Duration::from_millis(50);
+const TICK_MS: u32 = TICK.as_millis() as u32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Off,
+    // Slow even blink until the radio is up.
+    Booting,
+    // Radio up and listening: three quick flashes.
+    Joined,
+    // One flash per packet sent.
+    Tx,
+    // Two flashes for a packet received.
+    Rx,
+    // Fast blink burst.
+    Error,
+    // Short blip every few seconds for as long as the battery is low.
+    LowBattery,
+}
+
+impl Pattern {
+    // LED level and how long it is held, in ms.
+    fn steps(self) -> &'static [(bool, u32)] {
+        match self {
+            Pattern::Off => &[(false, 1000)],
+            Pattern::Booting => &[(true, 500), (false, 500)],
+            Pattern::Joined => &[
+                (true, 100),
+                (false, 100),
+                (true, 100),
+                (false, 100),
+                (true, 100),
+                (false, 300),
+            ],
+            Pattern::Tx => &[(true, 150), (false, 200)],
+            Pattern::Rx => &[(true, 50), (false, 100), (true, 50), (false, 200)],
+            Pattern::Error => &[(true, 50), (false, 50)],
+            Pattern::LowBattery => &[(true, 100), (false, 4900)],
+        }
+    }
+
+    // Background patterns repeat until replaced, the others play `plays`
+    // times over the background and then hand back to it.
+    fn plays(self) -> Option<u32> {
+        match self {
+            Pattern::Off | Pattern::Booting | Pattern::LowBattery => None,
+            Pattern::Joined | Pattern::Tx | Pattern::Rx => Some(1),
+            Pattern::Error => Some(10),
+        }
+    }
+}
+
+// Steps through the active pattern, one call to `tick` per `TICK`. Plain
+// state, the timer callback does the I/O.
+pub struct Sequencer {
+    background: Pattern,
+    current: Pattern,
+    plays_left: Option<u32>,
+    step: usize,
+    // Ticks left in the current step.
+    left: u32,
+}
+
+impl Sequencer {
+    pub fn new(background: Pattern) -> Self {
+        let mut this = Self {
+            background,
+            current: background,
+            plays_left: None,
+            step: 0,
+            left: 0,
+        };
+        this.start(background);
+        this
+    }
+
+    // Setting the running background again leaves its phase alone.
+    pub fn set_background(&mut self, pattern: Pattern) {
+        if pattern == self.background {
+            return;
+        }
+        self.background = pattern;
+        if self.plays_left.is_none() {
+            self.start(pattern);
+        }
+    }
+
+    // Plays a one-off pattern now, cutting short whatever is running.
+    pub fn play(&mut self, pattern: Pattern) {
+        match pattern.plays() {
+            Some(_) => self.start(pattern),
+            None => self.set_background(pattern),
+        }
+    }
+
+    fn start(&mut self, pattern: Pattern) {
+        self.current = pattern;
+        self.plays_left = pattern.plays();
+        self.step = 0;
+        self.left = ticks(pattern.steps()[0].1);
+    }
+
+    // Level for the tick that is starting.
+    pub fn tick(&mut self) -> bool {
+        if self.left == 0 {
+            self.advance();
+        }
+        self.left -= 1;
+        self.current.steps()[self.step].0
+    }
+
+    fn advance(&mut self) {
+        let steps = self.current.steps();
+        self.step += 1;
+        if self.step == steps.len() {
+            self.step = 0;
+            if let Some(plays) = &mut self.plays_left {
+                *plays -= 1;
+                if *plays == 0 {
+                    self.start(self.background);
+                    return;
+                }
+            }
+        }
+        self.left = ticks(self.current.steps()[self.step].1);
+    }
+}
+
+fn ticks(ms: u32) -> u32 {
+    ms.div_ceil(TICK_MS).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(sequencer: &mut Sequencer, ticks: usize) -> Vec<bool> {
+        (0..ticks).map(|_| sequencer.tick()).collect()
+    }
+
+    #[test]
+    fn one_off_hands_back_to_the_background() {
+        let mut sequencer = Sequencer::new(Pattern::Off);
+        sequencer.play(Pattern::Tx);
+
+        // 150 ms on and 200 ms off, rounded up to whole ticks.
+        let mut expected = vec![true; 3];
+        expected.extend([false; 4 + 5]);
+        assert_eq!(levels(&mut sequencer, 12), expected);
+    }
+
+    #[test]
+    fn background_changes_wait_for_a_one_off() {
+        let mut sequencer = Sequencer::new(Pattern::Off);
+        sequencer.play(Pattern::Error);
+        sequencer.set_background(Pattern::Booting);
+
+        // Ten 50 ms flashes, then the new background from its first step.
+        let error: Vec<bool> = (0..20).map(|i| i % 2 == 0).collect();
+        assert_eq!(levels(&mut sequencer, 20), error);
+        assert_eq!(levels(&mut sequencer, 10), [true; 10]);
+        assert_eq!(levels(&mut sequencer, 10), [false; 10]);
+    }
+
+    #[test]
+    fn setting_the_same_background_keeps_its_phase() {
+        let mut sequencer = Sequencer::new(Pattern::Booting);
+        assert_eq!(levels(&mut sequencer, 5), [true; 5]);
+
+        sequencer.set_background(Pattern::Booting);
+        let mut expected = vec![true; 5];
+        expected.extend([false; 10]);
+        assert_eq!(levels(&mut sequencer, 15), expected);
+    }
+}
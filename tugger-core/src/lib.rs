@@ -0,0 +1,9 @@
+// The firmware's logic that doesn't touch ESP-IDF, split out so its tests run
+// on the host: `cargo test` in this directory.
+pub mod battery;
+pub mod gesture;
+pub mod gnss;
+pub mod led;
+pub mod spi;
+pub mod spifault;
+pub mod spitrace;
@@ -0,0 +1,417 @@
+use embassy_futures::block_on;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{ErrorKind, ErrorType, Mode, Operation, SpiDevice};
+
+// Held for a whole transaction, CS included. Async so a waiting async device
+// yields to the executor; blocking devices wait on it with `block_on`, which
+// must not happen on the executor's own task while an async device there is
+// holding it across an await.
+pub type BusLock = Mutex<CriticalSectionRawMutex, ()>;
+
+// Bus settings for one device on a shared bus.
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceConfig {
+    pub mode: Mode,
+    pub frequency_hz: u32,
+}
+
+#[derive(Debug)]
+pub enum SharedSpiError<B, P> {
+    Bus(B),
+    ChipSelect(P),
+}
+
+impl<B, P> embedded_hal::spi::Error for SharedSpiError<B, P>
+where
+    B: embedded_hal::spi::Error,
+    P: core::fmt::Debug,
+{
+    fn kind(&self) -> ErrorKind {
+        match self {
+            SharedSpiError::Bus(e) => e.kind(),
+            SharedSpiError::ChipSelect(_) => ErrorKind::ChipSelectFault,
+        }
+    }
+}
+
+// One device on a shared bus. Owns the device's chip select and keeps it
+// asserted for the whole transaction, with the bus lock held so no other
+// device can be selected at the same time. `D` does the transfers in the
+// device's own mode and clock and must not drive a CS of its own; `DELAY`
+// carries out `Operation::DelayNs`.
+pub struct SharedSpiDevice<'a, D, CS, DELAY> {
+    bus: &'a BusLock,
+    device: D,
+    cs: CS,
+    delay: DELAY,
+}
+
+impl<'a, D, CS, DELAY> SharedSpiDevice<'a, D, CS, DELAY>
+where
+    CS: OutputPin,
+{
+    // CS is deselected straight away, it may power up low.
+    pub fn new(bus: &'a BusLock, device: D, mut cs: CS, delay: DELAY) -> Result<Self, CS::Error> {
+        cs.set_high()?;
+        Ok(Self {
+            bus,
+            device,
+            cs,
+            delay,
+        })
+    }
+}
+
+impl<'a, D, CS, DELAY> ErrorType for SharedSpiDevice<'a, D, CS, DELAY>
+where
+    D: SpiDevice,
+    CS: OutputPin,
+{
+    type Error = SharedSpiError<D::Error, CS::Error>;
+}
+
+// One step of a transaction: a run of transfers for the device, or a delay.
+enum Step<'o, 'b> {
+    Transfers(&'o mut [Operation<'b, u8>]),
+    Delay(u32),
+}
+
+// Splits `operations` at every `DelayNs`. The caller finishes each run of
+// transfers before it starts the delay after it, as the trait requires.
+struct Steps<'o, 'b> {
+    rest: &'o mut [Operation<'b, u8>],
+}
+
+impl<'o, 'b> Steps<'o, 'b> {
+    fn new(operations: &'o mut [Operation<'b, u8>]) -> Self {
+        Self { rest: operations }
+    }
+}
+
+impl<'o, 'b> Iterator for Steps<'o, 'b> {
+    type Item = Step<'o, 'b>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let operations = std::mem::take(&mut self.rest);
+        match operations {
+            [] => None,
+            [Operation::DelayNs(ns), rest @ ..] => {
+                let ns = *ns;
+                self.rest = rest;
+                Some(Step::Delay(ns))
+            }
+            _ => {
+                let end = operations
+                    .iter()
+                    .position(|op| matches!(op, Operation::DelayNs(_)))
+                    .unwrap_or(operations.len());
+                let (transfers, rest) = operations.split_at_mut(end);
+                self.rest = rest;
+                Some(Step::Transfers(transfers))
+            }
+        }
+    }
+}
+
+fn run<D, DELAY>(
+    device: &mut D,
+    delay: &mut DELAY,
+    operations: &mut [Operation<'_, u8>],
+) -> Result<(), D::Error>
+where
+    D: SpiDevice,
+    DELAY: DelayNs,
+{
+    for step in Steps::new(operations) {
+        match step {
+            Step::Transfers(transfers) => device.transaction(transfers)?,
+            Step::Delay(ns) => delay.delay_ns(ns),
+        }
+    }
+    Ok(())
+}
+
+impl<'a, D, CS, DELAY> SpiDevice for SharedSpiDevice<'a, D, CS, DELAY>
+where
+    D: SpiDevice,
+    CS: OutputPin,
+    DELAY: DelayNs,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let _bus = block_on(self.bus.lock());
+
+        self.cs.set_low().map_err(SharedSpiError::ChipSelect)?;
+        let result =
+            run(&mut self.device, &mut self.delay, operations).map_err(SharedSpiError::Bus);
+        // Deselect even after a failed transfer so the bus is left idle.
+        let deselect = self.cs.set_high().map_err(SharedSpiError::ChipSelect);
+
+        result.and(deselect)
+    }
+}
+
+// `SharedSpiDevice` for async drivers. Waiting for the bus and the transfers
+// themselves (DMA, completed from the SPI interrupt) both yield, so the
+// executor keeps running while the radio talks to the chip.
+pub struct AsyncSharedSpiDevice<'a, D, CS, DELAY> {
+    bus: &'a BusLock,
+    device: D,
+    cs: CS,
+    delay: DELAY,
+}
+
+impl<'a, D, CS, DELAY> AsyncSharedSpiDevice<'a, D, CS, DELAY>
+where
+    CS: OutputPin,
+{
+    // CS is deselected straight away, it may power up low.
+    pub fn new(bus: &'a BusLock, device: D, mut cs: CS, delay: DELAY) -> Result<Self, CS::Error> {
+        cs.set_high()?;
+        Ok(Self {
+            bus,
+            device,
+            cs,
+            delay,
+        })
+    }
+}
+
+impl<'a, D, CS, DELAY> ErrorType for AsyncSharedSpiDevice<'a, D, CS, DELAY>
+where
+    D: embedded_hal_async::spi::SpiDevice,
+    CS: OutputPin,
+{
+    type Error = SharedSpiError<D::Error, CS::Error>;
+}
+
+// `run` for async devices.
+async fn run_async<D, DELAY>(
+    device: &mut D,
+    delay: &mut DELAY,
+    operations: &mut [Operation<'_, u8>],
+) -> Result<(), D::Error>
+where
+    D: embedded_hal_async::spi::SpiDevice,
+    DELAY: embedded_hal_async::delay::DelayNs,
+{
+    for step in Steps::new(operations) {
+        match step {
+            Step::Transfers(transfers) => device.transaction(transfers).await?,
+            Step::Delay(ns) => delay.delay_ns(ns).await,
+        }
+    }
+    Ok(())
+}
+
+impl<'a, D, CS, DELAY> embedded_hal_async::spi::SpiDevice for AsyncSharedSpiDevice<'a, D, CS, DELAY>
+where
+    D: embedded_hal_async::spi::SpiDevice,
+    CS: OutputPin,
+    DELAY: embedded_hal_async::delay::DelayNs,
+{
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        let _bus = self.bus.lock().await;
+
+        self.cs.set_low().map_err(SharedSpiError::ChipSelect)?;
+        let result = run_async(&mut self.device, &mut self.delay, operations)
+            .await
+            .map_err(SharedSpiError::Bus);
+        // Deselect even after a failed transfer so the bus is left idle.
+        let deselect = self.cs.set_high().map_err(SharedSpiError::ChipSelect);
+
+        result.and(deselect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::convert::Infallible;
+    use std::rc::Rc;
+
+    use super::*;
+
+    // Nanoseconds since the start of the test, only moved by `MockDelay`.
+    type Clock = Rc<Cell<u64>>;
+
+    #[derive(Debug, PartialEq)]
+    enum Seen {
+        Select,
+        Deselect,
+        // A transaction on the device: when it ran and its transfer lengths.
+        Transaction(u64, Vec<usize>),
+    }
+
+    type Log = Rc<RefCell<Vec<Seen>>>;
+
+    struct MockDevice {
+        clock: Clock,
+        log: Log,
+    }
+
+    impl MockDevice {
+        fn record(&mut self, operations: &mut [Operation<'_, u8>]) {
+            let lengths = operations
+                .iter()
+                .map(|op| match op {
+                    Operation::Read(buf) => buf.len(),
+                    Operation::Write(buf) => buf.len(),
+                    Operation::Transfer(read, _) => read.len(),
+                    Operation::TransferInPlace(buf) => buf.len(),
+                    Operation::DelayNs(_) => panic!("delay passed through to the device"),
+                })
+                .collect();
+            self.log
+                .borrow_mut()
+                .push(Seen::Transaction(self.clock.get(), lengths));
+        }
+    }
+
+    impl ErrorType for MockDevice {
+        type Error = Infallible;
+    }
+
+    impl SpiDevice for MockDevice {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+            self.record(operations);
+            Ok(())
+        }
+    }
+
+    impl embedded_hal_async::spi::SpiDevice for MockDevice {
+        async fn transaction(
+            &mut self,
+            operations: &mut [Operation<'_, u8>],
+        ) -> Result<(), Infallible> {
+            self.record(operations);
+            Ok(())
+        }
+    }
+
+    struct MockCs(Log);
+
+    impl embedded_hal::digital::ErrorType for MockCs {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockCs {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(Seen::Select);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(Seen::Deselect);
+            Ok(())
+        }
+    }
+
+    struct MockDelay(Clock);
+
+    impl DelayNs for MockDelay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.0.set(self.0.get() + ns as u64);
+        }
+    }
+
+    impl embedded_hal_async::delay::DelayNs for MockDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.0.set(self.0.get() + ns as u64);
+        }
+    }
+
+    fn mocks() -> (Clock, Log, MockDevice, MockCs, MockDelay) {
+        let clock = Clock::default();
+        let log = Log::default();
+        let device = MockDevice {
+            clock: clock.clone(),
+            log: log.clone(),
+        };
+        let cs = MockCs(log.clone());
+        let delay = MockDelay(clock.clone());
+        (clock, log, device, cs, delay)
+    }
+
+    // A reset-style sequence: a command, a settle time, a read, then two
+    // delays back to back.
+    fn check(log: &Log, clock: &Clock) {
+        assert_eq!(
+            *log.borrow(),
+            [
+                Seen::Deselect,
+                Seen::Select,
+                Seen::Transaction(0, vec![2, 1]),
+                Seen::Transaction(5_000, vec![4]),
+                Seen::Deselect,
+            ]
+        );
+        assert_eq!(clock.get(), 5_000 + 300 + 700);
+    }
+
+    fn operations<'b>(status: &'b mut [u8], data: &'b mut [u8]) -> [Operation<'b, u8>; 6] {
+        [
+            Operation::Write(&[0x80, 0x00]),
+            Operation::TransferInPlace(status),
+            Operation::DelayNs(5_000),
+            Operation::Read(data),
+            Operation::DelayNs(300),
+            Operation::DelayNs(700),
+        ]
+    }
+
+    #[test]
+    fn delays_split_the_transaction() {
+        let (clock, log, device, cs, delay) = mocks();
+        let bus = BusLock::new(());
+        let mut spi = SharedSpiDevice::new(&bus, device, cs, delay).unwrap();
+
+        let (mut status, mut data) = ([0; 1], [0; 4]);
+        let mut ops = operations(&mut status, &mut data);
+        spi.transaction(&mut ops).unwrap();
+
+        check(&log, &clock);
+    }
+
+    #[test]
+    fn async_delays_split_the_transaction() {
+        let (clock, log, device, cs, delay) = mocks();
+        let bus = BusLock::new(());
+        let mut spi = AsyncSharedSpiDevice::new(&bus, device, cs, delay).unwrap();
+
+        let (mut status, mut data) = ([0; 1], [0; 4]);
+        let mut ops = operations(&mut status, &mut data);
+        block_on(embedded_hal_async::spi::SpiDevice::transaction(
+            &mut spi, &mut ops,
+        ))
+        .unwrap();
+
+        check(&log, &clock);
+    }
+
+    #[test]
+    fn leading_delay_runs_before_any_transfer() {
+        let (clock, log, mut device, _, mut delay) = mocks();
+        let mut data = [0; 3];
+        let mut ops = [Operation::DelayNs(1_000), Operation::Read(&mut data)];
+        run(&mut device, &mut delay, &mut ops).unwrap();
+
+        assert_eq!(*log.borrow(), [Seen::Transaction(1_000, vec![3])]);
+        assert_eq!(clock.get(), 1_000);
+    }
+
+    #[test]
+    fn no_delays_is_one_transaction() {
+        let (_, log, mut device, _, mut delay) = mocks();
+        let mut data = [0; 3];
+        let mut ops = [Operation::Write(&[1, 2]), Operation::Read(&mut data)];
+        run(&mut device, &mut delay, &mut ops).unwrap();
+
+        assert_eq!(*log.borrow(), [Seen::Transaction(0, vec![2, 3])]);
+    }
+}
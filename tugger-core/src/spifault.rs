@@ -0,0 +1,552 @@
+// Fault injection for tests: wraps a (mock) SPI device, bus or busy pin and
+// breaks it on a schedule, to drive the radio and display error paths the
+// way flaky wiring would.
+
+use std::convert::Infallible;
+use std::future::pending;
+
+use embedded_hal::digital::{self, InputPin, OutputPin};
+use embedded_hal::spi::{self, ErrorKind, Operation, SpiBus, SpiDevice};
+use embedded_hal_async::digital::Wait;
+
+// Which calls a rule applies to, counted from 0.
+#[derive(Clone, Copy, Debug)]
+pub enum When {
+    At(usize),
+    // Every nth call: n - 1, 2n - 1, ...
+    Every(usize),
+    // This call and all after it, for a fault that never clears.
+    From(usize),
+}
+
+impl When {
+    fn matches(self, call: usize) -> bool {
+        match self {
+            When::At(n) => call == n,
+            When::Every(n) => n > 0 && (call + 1).is_multiple_of(n),
+            When::From(n) => call >= n,
+        }
+    }
+}
+
+// Rules checked in order on every call, the first match wins.
+#[derive(Clone, Debug)]
+pub struct Schedule<F> {
+    rules: Vec<(When, F)>,
+    calls: usize,
+}
+
+impl<F: Copy> Schedule<F> {
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            calls: 0,
+        }
+    }
+
+    pub fn with(mut self, when: When, fault: F) -> Self {
+        self.rules.push((when, fault));
+        self
+    }
+
+    // Calls seen so far.
+    pub fn calls(&self) -> usize {
+        self.calls
+    }
+
+    fn next(&mut self) -> Option<F> {
+        let call = self.calls;
+        self.calls += 1;
+        self.rules
+            .iter()
+            .find(|(when, _)| when.matches(call))
+            .map(|&(_, fault)| fault)
+    }
+}
+
+impl<F: Copy> Default for Schedule<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Fault {
+    // The transfer fails with `FaultError::Injected`.
+    Error(ErrorKind),
+    // The transfer goes through but every byte read is XORed with the mask,
+    // like a bad MISO contact.
+    Corrupt(u8),
+    // Only the first n bytes of the transaction go over the bus, then it
+    // fails with `ErrorKind::Other`, like a transfer cut short by a reset.
+    // Shorter transactions go through.
+    Short(usize),
+}
+
+#[derive(Debug)]
+pub enum FaultError<E> {
+    Device(E),
+    Injected(ErrorKind),
+}
+
+impl<E: spi::Error> spi::Error for FaultError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            FaultError::Device(e) => e.kind(),
+            FaultError::Injected(kind) => *kind,
+        }
+    }
+}
+
+fn corrupt(operations: &mut [Operation<'_, u8>], mask: u8) {
+    for op in operations {
+        let read: &mut [u8] = match op {
+            Operation::Read(buf) | Operation::TransferInPlace(buf) => buf,
+            Operation::Transfer(read, _) => read,
+            Operation::Write(_) | Operation::DelayNs(_) => continue,
+        };
+        read.iter_mut().for_each(|b| *b ^= mask);
+    }
+}
+
+fn op_len(op: &Operation<'_, u8>) -> usize {
+    match op {
+        Operation::Read(buf) | Operation::TransferInPlace(buf) => buf.len(),
+        Operation::Write(buf) => buf.len(),
+        Operation::Transfer(read, write) => read.len().max(write.len()),
+        Operation::DelayNs(_) => 0,
+    }
+}
+
+// The operations cut to their first `limit` bytes, `None` if they fit.
+fn truncate<'a, 'b>(
+    operations: &'a mut [Operation<'b, u8>],
+    mut limit: usize,
+) -> Option<Vec<Operation<'a, u8>>> {
+    if operations.iter().map(op_len).sum::<usize>() <= limit {
+        return None;
+    }
+
+    let mut short = Vec::new();
+    for op in operations {
+        if limit == 0 {
+            break;
+        }
+        let n = op_len(op).min(limit);
+        limit -= n;
+        short.push(match op {
+            Operation::Read(buf) => Operation::Read(&mut buf[..n]),
+            Operation::Write(buf) => Operation::Write(&buf[..n]),
+            Operation::Transfer(read, write) => {
+                let (r, w) = (read.len().min(n), write.len().min(n));
+                Operation::Transfer(&mut read[..r], &write[..w])
+            }
+            Operation::TransferInPlace(buf) => Operation::TransferInPlace(&mut buf[..n]),
+            Operation::DelayNs(ns) => Operation::DelayNs(*ns),
+        });
+    }
+    Some(short)
+}
+
+// A `SpiDevice` that fails or corrupts scheduled transactions.
+pub struct FaultyDevice<D> {
+    device: D,
+    pub schedule: Schedule<Fault>,
+}
+
+impl<D> FaultyDevice<D> {
+    pub fn new(device: D, schedule: Schedule<Fault>) -> Self {
+        Self { device, schedule }
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D: spi::ErrorType> spi::ErrorType for FaultyDevice<D> {
+    type Error = FaultError<D::Error>;
+}
+
+impl<D: SpiDevice> SpiDevice for FaultyDevice<D> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let fault = self.schedule.next();
+        if let Some(Fault::Error(kind)) = fault {
+            return Err(FaultError::Injected(kind));
+        }
+        if let Some(Fault::Short(limit)) = fault {
+            if let Some(mut short) = truncate(operations, limit) {
+                self.device
+                    .transaction(&mut short)
+                    .map_err(FaultError::Device)?;
+                return Err(FaultError::Injected(ErrorKind::Other));
+            }
+        }
+
+        self.device
+            .transaction(operations)
+            .map_err(FaultError::Device)?;
+        if let Some(Fault::Corrupt(mask)) = fault {
+            corrupt(operations, mask);
+        }
+        Ok(())
+    }
+}
+
+impl<D: embedded_hal_async::spi::SpiDevice> embedded_hal_async::spi::SpiDevice for FaultyDevice<D> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        let fault = self.schedule.next();
+        if let Some(Fault::Error(kind)) = fault {
+            return Err(FaultError::Injected(kind));
+        }
+        if let Some(Fault::Short(limit)) = fault {
+            if let Some(mut short) = truncate(operations, limit) {
+                self.device
+                    .transaction(&mut short)
+                    .await
+                    .map_err(FaultError::Device)?;
+                return Err(FaultError::Injected(ErrorKind::Other));
+            }
+        }
+
+        self.device
+            .transaction(operations)
+            .await
+            .map_err(FaultError::Device)?;
+        if let Some(Fault::Corrupt(mask)) = fault {
+            corrupt(operations, mask);
+        }
+        Ok(())
+    }
+}
+
+// The same for a `SpiBus`, where every call (flush included) is one step of
+// the schedule.
+pub struct FaultyBus<B> {
+    bus: B,
+    pub schedule: Schedule<Fault>,
+}
+
+impl<B> FaultyBus<B> {
+    pub fn new(bus: B, schedule: Schedule<Fault>) -> Self {
+        Self { bus, schedule }
+    }
+
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+
+    // Fails the call if scheduled, otherwise returns the mask for its reads.
+    // A bus call has no transaction to cut short, `Short` fails it outright.
+    fn step<E>(&mut self) -> Result<u8, FaultError<E>> {
+        match self.schedule.next() {
+            Some(Fault::Error(kind)) => Err(FaultError::Injected(kind)),
+            Some(Fault::Short(_)) => Err(FaultError::Injected(ErrorKind::Other)),
+            Some(Fault::Corrupt(mask)) => Ok(mask),
+            None => Ok(0),
+        }
+    }
+}
+
+impl<B: spi::ErrorType> spi::ErrorType for FaultyBus<B> {
+    type Error = FaultError<B::Error>;
+}
+
+impl<B: SpiBus> SpiBus for FaultyBus<B> {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        let mask = self.step()?;
+        self.bus.read(words).map_err(FaultError::Device)?;
+        words.iter_mut().for_each(|b| *b ^= mask);
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.step()?;
+        self.bus.write(words).map_err(FaultError::Device)
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        let mask = self.step()?;
+        self.bus.transfer(read, write).map_err(FaultError::Device)?;
+        read.iter_mut().for_each(|b| *b ^= mask);
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        let mask = self.step()?;
+        self.bus
+            .transfer_in_place(words)
+            .map_err(FaultError::Device)?;
+        words.iter_mut().for_each(|b| *b ^= mask);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.step()?;
+        self.bus.flush().map_err(FaultError::Device)
+    }
+}
+
+// A busy line that sticks: on scheduled reads it reports the given level
+// (true = high) instead of the real one. Each `is_high`/`is_low` and each
+// wait is a step, a wait for the other level never returns.
+pub struct StuckPin<P> {
+    pin: P,
+    pub schedule: Schedule<bool>,
+}
+
+impl<P> StuckPin<P> {
+    pub fn new(pin: P, schedule: Schedule<bool>) -> Self {
+        Self { pin, schedule }
+    }
+}
+
+impl<P: digital::ErrorType> digital::ErrorType for StuckPin<P> {
+    type Error = P::Error;
+}
+
+impl<P: InputPin> InputPin for StuckPin<P> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        match self.schedule.next() {
+            Some(level) => Ok(level),
+            None => self.pin.is_high(),
+        }
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+impl<P: Wait> Wait for StuckPin<P> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        match self.schedule.next() {
+            Some(true) => Ok(()),
+            Some(false) => pending().await,
+            None => self.pin.wait_for_high().await,
+        }
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        match self.schedule.next() {
+            Some(false) => Ok(()),
+            Some(true) => pending().await,
+            None => self.pin.wait_for_low().await,
+        }
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        match self.schedule.next() {
+            Some(_) => pending().await,
+            None => self.pin.wait_for_rising_edge().await,
+        }
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        match self.schedule.next() {
+            Some(_) => pending().await,
+            None => self.pin.wait_for_falling_edge().await,
+        }
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        match self.schedule.next() {
+            Some(_) => pending().await,
+            None => self.pin.wait_for_any_edge().await,
+        }
+    }
+}
+
+// A device that takes every transaction and reads zeros, for a wrapper to
+// break.
+pub struct NullDevice;
+
+fn zero(operations: &mut [Operation<'_, u8>]) {
+    for op in operations {
+        match op {
+            Operation::Read(buf) | Operation::TransferInPlace(buf) => buf.fill(0),
+            Operation::Transfer(read, _) => read.fill(0),
+            Operation::Write(_) | Operation::DelayNs(_) => {}
+        }
+    }
+}
+
+impl spi::ErrorType for NullDevice {
+    type Error = Infallible;
+}
+
+impl SpiDevice for NullDevice {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        zero(operations);
+        Ok(())
+    }
+}
+
+impl SpiBus for NullDevice {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        words.fill(0);
+        Ok(())
+    }
+
+    fn write(&mut self, _words: &[u8]) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], _write: &[u8]) -> Result<(), Infallible> {
+        read.fill(0);
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        words.fill(0);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl embedded_hal_async::spi::SpiDevice for NullDevice {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Infallible> {
+        zero(operations);
+        Ok(())
+    }
+}
+
+// A line held at one level (true = high). Writes are ignored, so it also
+// stands in for outputs nobody reads back.
+pub struct FixedPin(pub bool);
+
+impl digital::ErrorType for FixedPin {
+    type Error = Infallible;
+}
+
+impl InputPin for FixedPin {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(self.0)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(!self.0)
+    }
+}
+
+impl OutputPin for FixedPin {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl Wait for FixedPin {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        if !self.0 {
+            pending::<()>().await;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        if self.0 {
+            pending::<()>().await;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        pending().await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        pending().await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fires(when: When, calls: usize) -> Vec<usize> {
+        let mut schedule = Schedule::new().with(when, ());
+        (0..calls).filter(|_| schedule.next().is_some()).collect()
+    }
+
+    #[test]
+    fn schedule_rules() {
+        assert_eq!(fires(When::At(2), 6), [2]);
+        assert_eq!(fires(When::Every(3), 7), [2, 5]);
+        assert_eq!(fires(When::Every(0), 3), []);
+        assert_eq!(fires(When::From(4), 6), [4, 5]);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let mut schedule = Schedule::new()
+            .with(When::At(1), Fault::Corrupt(0xff))
+            .with(When::From(0), Fault::Error(ErrorKind::Overrun));
+        assert!(matches!(schedule.next(), Some(Fault::Error(_))));
+        assert!(matches!(schedule.next(), Some(Fault::Corrupt(0xff))));
+        assert_eq!(schedule.calls(), 2);
+    }
+
+    #[test]
+    fn device_faults() {
+        let schedule = Schedule::new()
+            .with(When::At(0), Fault::Corrupt(0x0f))
+            .with(When::At(1), Fault::Error(ErrorKind::ModeFault))
+            .with(When::From(2), Fault::Short(3));
+        let mut spi = FaultyDevice::new(NullDevice, schedule);
+
+        let mut read = [0; 2];
+        spi.transaction(&mut [Operation::Read(&mut read)]).unwrap();
+        assert_eq!(read, [0x0f, 0x0f]);
+
+        let e = spi.transaction(&mut []).unwrap_err();
+        assert_eq!(spi::Error::kind(&e), ErrorKind::ModeFault);
+
+        // Three bytes go through, four don't.
+        spi.transaction(&mut [Operation::Write(&[1, 2]), Operation::Write(&[3])])
+            .unwrap();
+        let mut read = [0xaa; 2];
+        let e = spi
+            .transaction(&mut [Operation::Write(&[1, 2]), Operation::Read(&mut read)])
+            .unwrap_err();
+        assert_eq!(spi::Error::kind(&e), ErrorKind::Other);
+        assert_eq!(read, [0, 0xaa]);
+
+        let NullDevice = spi.into_inner();
+    }
+
+    #[test]
+    fn bus_faults() {
+        let schedule = Schedule::new()
+            .with(When::At(0), Fault::Corrupt(0x80))
+            .with(When::At(1), Fault::Short(1))
+            .with(When::At(2), Fault::Error(ErrorKind::FrameFormat));
+        let mut bus = FaultyBus::new(NullDevice, schedule);
+
+        let mut words = [0; 2];
+        bus.transfer(&mut words, &[1, 2]).unwrap();
+        assert_eq!(words, [0x80, 0x80]);
+        assert!(bus.transfer_in_place(&mut words).is_err());
+        assert!(bus.read(&mut words).is_err());
+        bus.write(&words).unwrap();
+        bus.flush().unwrap();
+
+        let NullDevice = bus.into_inner();
+    }
+}
@@ -0,0 +1,606 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+use log::*;
+
+// Records kept when built with the `spi-trace` feature, oldest dropped first.
+// Without it the wrappers pass straight through.
+pub const CAPACITY: usize = if cfg!(feature = "spi-trace") { 64 } else { 0 };
+
+// Bytes kept per operation. Commands and register reads fit, a display frame
+// keeps its first bytes and its length instead of a whole framebuffer.
+pub const PAYLOAD_CAP: usize = 32;
+
+// Starts every record line of a dump, so `parse_dump` can find them among
+// the rest of the log.
+const MARKER: &str = "spi-trace ";
+
+// The bytes of one operation, cut to `PAYLOAD_CAP`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Payload {
+    pub len: usize,
+    pub head: Vec<u8>,
+}
+
+impl Payload {
+    fn capture(data: &[u8]) -> Self {
+        Self {
+            len: data.len(),
+            head: data[..data.len().min(PAYLOAD_CAP)].to_vec(),
+        }
+    }
+}
+
+// One operation as it went over the bus. Reads hold what the device sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceOp {
+    Write(Payload),
+    Read(Payload),
+    Transfer { write: Payload, read: Payload },
+    TransferInPlace { write: Payload, read: Payload },
+    DelayNs(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    // Owned when parsed back from a dump.
+    pub device: Cow<'static, str>,
+    // Since the buffer was created.
+    pub at: Duration,
+    pub took: Duration,
+    pub ops: Vec<TraceOp>,
+    pub ok: bool,
+}
+
+// `<len>:<hex of the kept bytes>`.
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.len)?;
+        self.head.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+impl fmt::Display for TraceOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceOp::Write(data) => write!(f, "w:{}", data),
+            TraceOp::Read(data) => write!(f, "r:{}", data),
+            TraceOp::Transfer { write, read } => write!(f, "t:{}/{}", write, read),
+            TraceOp::TransferInPlace { write, read } => write!(f, "x:{}/{}", write, read),
+            TraceOp::DelayNs(ns) => write!(f, "d:{}", ns),
+        }
+    }
+}
+
+// One line of a dump, times in µs:
+// `spi-trace <at> <device> ok|failed <took> <op>...`.
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{} {} {} {}",
+            MARKER,
+            self.at.as_micros(),
+            self.device,
+            if self.ok { "ok" } else { "failed" },
+            self.took.as_micros()
+        )?;
+        self.ops.iter().try_for_each(|op| write!(f, " {}", op))
+    }
+}
+
+struct Ring {
+    records: VecDeque<TraceRecord>,
+    dropped: usize,
+}
+
+// Ring buffer of the last transactions, shared by every traced device.
+#[derive(Clone)]
+pub struct TraceBuffer {
+    ring: Arc<Mutex<Ring>>,
+    capacity: usize,
+    origin: Instant,
+}
+
+impl TraceBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ring: Arc::new(Mutex::new(Ring {
+                records: VecDeque::with_capacity(capacity),
+                dropped: 0,
+            })),
+            capacity,
+            origin: Instant::now(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    fn push(&self, record: TraceRecord) {
+        trace!(
+            "spi {} {:?} in {:?}: {:02x?}",
+            record.device,
+            record.ok,
+            record.took,
+            record.ops
+        );
+
+        let mut ring = self.ring.lock().unwrap();
+        if ring.records.len() == self.capacity {
+            ring.records.pop_front();
+            ring.dropped += 1;
+        }
+        ring.records.push_back(record);
+    }
+
+    // Copy of the buffered records, oldest first.
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.ring.lock().unwrap().records.iter().cloned().collect()
+    }
+
+    // Logs the buffer, for when a driver has just failed. `parse_dump` reads
+    // it back.
+    pub fn dump(&self) {
+        if !self.enabled() {
+            return;
+        }
+
+        let records = self.records();
+        let dropped = self.ring.lock().unwrap().dropped;
+        info!(
+            "SPI trace: {} transactions ({} older dropped)",
+            records.len(),
+            dropped
+        );
+        for record in &records {
+            info!("  {}", record);
+        }
+    }
+}
+
+// What the operations write, taken before the transaction since
+// `TransferInPlace` overwrites it.
+fn writes(operations: &[Operation<'_, u8>]) -> Vec<Option<Payload>> {
+    operations
+        .iter()
+        .map(|op| match op {
+            Operation::TransferInPlace(buf) => Some(Payload::capture(buf)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn record(operations: &[Operation<'_, u8>], writes: Vec<Option<Payload>>) -> Vec<TraceOp> {
+    operations
+        .iter()
+        .zip(writes)
+        .map(|(op, write)| match op {
+            Operation::Write(buf) => TraceOp::Write(Payload::capture(buf)),
+            Operation::Read(buf) => TraceOp::Read(Payload::capture(buf)),
+            Operation::Transfer(read, write) => TraceOp::Transfer {
+                write: Payload::capture(write),
+                read: Payload::capture(read),
+            },
+            Operation::TransferInPlace(buf) => TraceOp::TransferInPlace {
+                write: write.unwrap_or_default(),
+                read: Payload::capture(buf),
+            },
+            Operation::DelayNs(ns) => TraceOp::DelayNs(*ns),
+        })
+        .collect()
+}
+
+// Records every transaction of `device` into a `TraceBuffer`. Works for both
+// blocking and async devices.
+pub struct Traced<D> {
+    device: D,
+    name: &'static str,
+    buffer: TraceBuffer,
+}
+
+impl<D> Traced<D> {
+    pub fn new(device: D, name: &'static str, buffer: TraceBuffer) -> Self {
+        Self {
+            device,
+            name,
+            buffer,
+        }
+    }
+
+    fn finish(
+        &self,
+        operations: &[Operation<'_, u8>],
+        writes: Vec<Option<Payload>>,
+        start: Instant,
+        ok: bool,
+    ) {
+        self.buffer.push(TraceRecord {
+            device: Cow::Borrowed(self.name),
+            at: start - self.buffer.origin,
+            took: start.elapsed(),
+            ops: record(operations, writes),
+            ok,
+        });
+    }
+}
+
+impl<D: ErrorType> ErrorType for Traced<D> {
+    type Error = D::Error;
+}
+
+impl<D: SpiDevice> SpiDevice for Traced<D> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        if !self.buffer.enabled() {
+            return self.device.transaction(operations);
+        }
+
+        let writes = writes(operations);
+        let start = Instant::now();
+        let result = self.device.transaction(operations);
+        self.finish(operations, writes, start, result.is_ok());
+        result
+    }
+}
+
+impl<D: embedded_hal_async::spi::SpiDevice> embedded_hal_async::spi::SpiDevice for Traced<D> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        if !self.buffer.enabled() {
+            return self.device.transaction(operations).await;
+        }
+
+        let writes = writes(operations);
+        let start = Instant::now();
+        let result = self.device.transaction(operations).await;
+        self.finish(operations, writes, start, result.is_ok());
+        result
+    }
+}
+
+pub use replay::{parse_dump, Replay, ReplayError};
+
+// Plays a recorded trace back to a driver, checking that it writes the same
+// bytes and handing it the reads the real device answered with.
+mod replay {
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    use embedded_hal::spi::{ErrorKind, ErrorType, Operation, SpiDevice};
+
+    use super::{Payload, TraceOp, TraceRecord, MARKER};
+
+    // Reads back the records `TraceBuffer::dump` logged, e.g. from a serial
+    // capture. Lines without the marker are skipped, log prefixes are fine.
+    pub fn parse_dump(text: &str) -> anyhow::Result<Vec<TraceRecord>> {
+        text.lines()
+            .filter_map(|line| line.find(MARKER).map(|i| &line[i + MARKER.len()..]))
+            .map(|line| {
+                parse_record(line).ok_or_else(|| anyhow::anyhow!("Bad trace line {:?}", line))
+            })
+            .collect()
+    }
+
+    fn parse_record(line: &str) -> Option<TraceRecord> {
+        let mut fields = line.split_whitespace();
+        let at = Duration::from_micros(fields.next()?.parse().ok()?);
+        let device = fields.next()?.to_string().into();
+        let ok = match fields.next()? {
+            "ok" => true,
+            "failed" => false,
+            _ => return None,
+        };
+        let took = Duration::from_micros(fields.next()?.parse().ok()?);
+        let ops = fields.map(parse_op).collect::<Option<_>>()?;
+        Some(TraceRecord {
+            device,
+            at,
+            took,
+            ops,
+            ok,
+        })
+    }
+
+    fn parse_op(field: &str) -> Option<TraceOp> {
+        let (kind, rest) = field.split_once(':')?;
+        let pair = || -> Option<(Payload, Payload)> {
+            let (write, read) = rest.split_once('/')?;
+            Some((parse_payload(write)?, parse_payload(read)?))
+        };
+        Some(match kind {
+            "w" => TraceOp::Write(parse_payload(rest)?),
+            "r" => TraceOp::Read(parse_payload(rest)?),
+            "t" => {
+                let (write, read) = pair()?;
+                TraceOp::Transfer { write, read }
+            }
+            "x" => {
+                let (write, read) = pair()?;
+                TraceOp::TransferInPlace { write, read }
+            }
+            "d" => TraceOp::DelayNs(rest.parse().ok()?),
+            _ => return None,
+        })
+    }
+
+    fn parse_payload(field: &str) -> Option<Payload> {
+        let (len, hex) = field.split_once(':')?;
+        let len = len.parse().ok()?;
+        let head = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        (head.len() <= len).then_some(Payload { len, head })
+    }
+
+    impl Payload {
+        // Whether `data` is what was captured, as far as it was kept.
+        fn matches(&self, data: &[u8]) -> bool {
+            data.len() == self.len && data.starts_with(&self.head)
+        }
+
+        // Hands back what was captured. Bytes past the cap were not kept and
+        // read as zeros.
+        fn fill(&self, buf: &mut [u8]) {
+            let (head, tail) = buf.split_at_mut(self.head.len());
+            head.copy_from_slice(&self.head);
+            tail.fill(0);
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum ReplayError {
+        // The driver ran more transactions than were recorded.
+        Exhausted,
+        // Transaction `transaction`, operation `op` differs from the trace.
+        Mismatch { transaction: usize, op: usize },
+        // The device failed this transaction when it was recorded.
+        Recorded { transaction: usize },
+    }
+
+    impl embedded_hal::spi::Error for ReplayError {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    pub struct Replay {
+        records: VecDeque<TraceRecord>,
+        done: usize,
+    }
+
+    impl Replay {
+        // Only the records of `device` are replayed, a trace holds every
+        // device on the bus.
+        pub fn new(records: impl IntoIterator<Item = TraceRecord>, device: &str) -> Self {
+            Self {
+                records: records.into_iter().filter(|r| r.device == device).collect(),
+                done: 0,
+            }
+        }
+
+        // Whether the driver went through the whole trace.
+        pub fn finished(&self) -> bool {
+            self.records.is_empty()
+        }
+
+        fn play(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ReplayError> {
+            let transaction = self.done;
+            let record = self.records.pop_front().ok_or(ReplayError::Exhausted)?;
+            self.done += 1;
+
+            let mismatch = |op| ReplayError::Mismatch { transaction, op };
+            if record.ops.len() != operations.len() {
+                return Err(mismatch(operations.len().min(record.ops.len())));
+            }
+
+            for (i, (op, recorded)) in operations.iter_mut().zip(&record.ops).enumerate() {
+                match (op, recorded) {
+                    (Operation::Write(buf), TraceOp::Write(data)) if data.matches(buf) => {}
+                    (Operation::Read(buf), TraceOp::Read(data)) if buf.len() == data.len => {
+                        data.fill(buf);
+                    }
+                    (Operation::Transfer(read, write), TraceOp::Transfer { write: w, read: r })
+                        if w.matches(write) && read.len() == r.len =>
+                    {
+                        r.fill(read);
+                    }
+                    (
+                        Operation::TransferInPlace(buf),
+                        TraceOp::TransferInPlace { write: w, read: r },
+                    ) if w.matches(buf) => {
+                        r.fill(buf);
+                    }
+                    (Operation::DelayNs(ns), TraceOp::DelayNs(recorded)) if ns == recorded => {}
+                    _ => return Err(mismatch(i)),
+                }
+            }
+
+            // A transaction that failed on the device fails again here, so
+            // the driver's error path runs the same way.
+            if record.ok {
+                Ok(())
+            } else {
+                Err(ReplayError::Recorded { transaction })
+            }
+        }
+    }
+
+    impl ErrorType for Replay {
+        type Error = ReplayError;
+    }
+
+    impl SpiDevice for Replay {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+            self.play(operations)
+        }
+    }
+
+    impl embedded_hal_async::spi::SpiDevice for Replay {
+        async fn transaction(
+            &mut self,
+            operations: &mut [Operation<'_, u8>],
+        ) -> Result<(), Self::Error> {
+            self.play(operations)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    // A radio self-test captured on a Wireless Paper V1.1, with the display
+    // traffic around it.
+    const SELFTEST_RADIO: &str = include_str!("../tests/fixtures/selftest-radio.log");
+
+    // Answers every read with `0xa5`.
+    struct Echo;
+
+    impl ErrorType for Echo {
+        type Error = Infallible;
+    }
+
+    impl SpiDevice for Echo {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+            for op in operations {
+                match op {
+                    Operation::Read(buf) | Operation::TransferInPlace(buf) => buf.fill(0xa5),
+                    Operation::Transfer(read, _) => read.fill(0xa5),
+                    Operation::Write(_) | Operation::DelayNs(_) => {}
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn payloads_are_capped() {
+        let buffer = TraceBuffer::new(4);
+        let mut spi = Traced::new(Echo, "display", buffer.clone());
+        let frame = [0x11; 4736];
+        let mut status = [0; 2];
+        spi.transaction(&mut [Operation::Write(&frame), Operation::Read(&mut status)])
+            .unwrap();
+
+        let records = buffer.records();
+        assert_eq!(
+            records[0].ops,
+            [
+                TraceOp::Write(Payload {
+                    len: 4736,
+                    head: vec![0x11; PAYLOAD_CAP],
+                }),
+                TraceOp::Read(Payload {
+                    len: 2,
+                    head: vec![0xa5; 2],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn dump_lines_parse_back() {
+        let record = TraceRecord {
+            device: "radio".into(),
+            at: Duration::from_micros(1_250_000),
+            took: Duration::from_micros(85),
+            ops: vec![
+                TraceOp::Write(Payload {
+                    len: 3,
+                    head: vec![0x0d, 0x07, 0x40],
+                }),
+                TraceOp::DelayNs(500),
+                TraceOp::Transfer {
+                    write: Payload {
+                        len: 2,
+                        head: vec![0x1d, 0x07],
+                    },
+                    read: Payload {
+                        len: 40,
+                        head: vec![0; PAYLOAD_CAP],
+                    },
+                },
+                TraceOp::TransferInPlace {
+                    write: Payload::default(),
+                    read: Payload::default(),
+                },
+            ],
+            ok: false,
+        };
+
+        let line = format!("I (1250) tugger_core::spitrace:   {}", record);
+        assert_eq!(parse_dump(&line).unwrap(), [record]);
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert!(parse_dump("spi-trace 10 radio maybe 5 w:1:00").is_err());
+        assert!(parse_dump("spi-trace 10 radio ok 5 w:1:0").is_err());
+        assert!(parse_dump("spi-trace 10 radio ok 5 w:1:0000").is_err());
+        assert!(parse_dump("spi-trace 10 radio ok 5 q:1:00").is_err());
+        assert_eq!(parse_dump("SPI trace: 0 transactions").unwrap(), []);
+    }
+
+    #[test]
+    fn capped_writes_match_on_length_and_head() {
+        let replay = |frame: &[u8]| {
+            let mut spi = Replay::new(parse_dump(SELFTEST_RADIO).unwrap(), "display");
+            spi.transaction(&mut [Operation::Write(&[0x24])]).unwrap();
+            spi.transaction(&mut [Operation::Write(frame)])
+        };
+        let mismatch = Err(ReplayError::Mismatch {
+            transaction: 1,
+            op: 0,
+        });
+
+        let mut frame = vec![0x11; 4736];
+        assert_eq!(replay(&frame), Ok(()));
+        assert_eq!(replay(&frame[..4735]), mismatch);
+        // Bytes past the cap weren't kept, so they can't be compared.
+        frame[4000] = 0;
+        assert_eq!(replay(&frame), Ok(()));
+        frame[0] = 0;
+        assert_eq!(replay(&frame), mismatch);
+    }
+
+    #[test]
+    fn reads_past_the_cap_are_zeros() {
+        let line = format!("spi-trace 5 radio ok 40 w:2:1e00 r:40:{}", "5a".repeat(32));
+        let mut spi = Replay::new(parse_dump(&line).unwrap(), "radio");
+        let mut data = [0xff; 40];
+        spi.transaction(&mut [Operation::Write(&[0x1e, 0]), Operation::Read(&mut data)])
+            .unwrap();
+
+        assert_eq!(data[..PAYLOAD_CAP], [0x5a; PAYLOAD_CAP]);
+        assert_eq!(data[PAYLOAD_CAP..], [0; 8]);
+    }
+
+    #[test]
+    fn diverging_driver_is_caught() {
+        let records = parse_dump(SELFTEST_RADIO).unwrap();
+        let mut spi = Replay::new(records, "radio");
+        let mut data = [0; 16];
+        let result = spi.transaction(&mut [
+            Operation::Write(&[0x1d, 0x07, 0x40, 0]),
+            Operation::Read(&mut data),
+        ]);
+
+        assert_eq!(
+            result,
+            Err(ReplayError::Mismatch {
+                transaction: 0,
+                op: 0
+            })
+        );
+    }
+}